@@ -0,0 +1,303 @@
+use crate::{
+    control::connect_control,
+    nodes::{Edge, Node, NodeType},
+    parser::ast::Operator,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Most computations a structure is shared by, the inputs of the largest multiplexer
+const MAX_SHARED: usize = 4;
+
+/// Steps a value is needed in: from the step it is computed or written at, to the last step reading it
+#[derive(Debug, Clone, Copy)]
+struct LiveRange {
+    start: usize,
+    end: usize,
+}
+
+/// What the allocation shared
+#[derive(Debug, Default)]
+pub struct Allocation {
+    /// Operator structures removed, their computations moved to another one
+    pub operators: usize,
+    /// Registers removed, their variables stored in the register of another one
+    pub registers: usize,
+    /// Multiplexers added in front of the shared operators
+    pub muxes: usize,
+}
+
+/// The operators worth a multiplexer on each input when shared: the adder is several times larger than a gate
+fn is_large(node: &NodeType) -> bool {
+    matches!(node, NodeType::Operator(Operator::Add))
+}
+
+/// Share the large structures between the computations that never run at the same time.
+/// The live range of each temporary goes from the step it is computed at to the last step using it, and the
+/// operators whose ranges do not overlap are bound to a single structure, with a multiplexer on each of its inputs
+/// selecting the operands of the step running. A variable whose first use is a write does not need the value it was
+/// declared with, so it is stored in the register of a variable no longer used by then
+pub fn share_structures(nodes: &mut Vec<Node>, edges: &mut Vec<Edge>) -> Allocation {
+    let mut allocation = Allocation::default();
+    let steps: HashMap<String, usize> = nodes
+        .iter()
+        .map(|node| (node.id.clone(), node.step))
+        .collect();
+
+    // Operators, by kind of structure
+    let mut kinds: Vec<(&'static str, Vec<(usize, LiveRange)>)> = Vec::new();
+    for (i, node) in nodes.iter().enumerate() {
+        if !is_large(&node.node) {
+            continue;
+        }
+
+        let name = NodeType::get_name(node.node.clone());
+        let end = edges
+            .iter()
+            .filter(|edge| edge.from_node == node.id)
+            .map(|edge| steps[&edge.to_node])
+            .fold(node.step, usize::max);
+        let range = LiveRange {
+            start: node.step,
+            end,
+        };
+
+        match kinds.iter_mut().find(|(kind, _)| *kind == name) {
+            Some((_, ranges)) => ranges.push((i, range)),
+            None => kinds.push((name, vec![(i, range)])),
+        }
+    }
+
+    // Registers, before the operators move to the multiplexers and change the steps their values are read at
+    let mut register_ranges = Vec::new();
+    let mut needs_value = Vec::new();
+    for (i, node) in nodes.iter().enumerate() {
+        if !matches!(node.node, NodeType::Register { .. }) {
+            continue;
+        }
+
+        let reads: Vec<usize> = edges
+            .iter()
+            .filter(|edge| edge.from_node == node.id)
+            .map(|edge| steps[&edge.to_node])
+            .collect();
+        let writes: Vec<usize> = edges
+            .iter()
+            .filter(|edge| edge.to_node == node.id)
+            .map(|edge| steps[&edge.from_node])
+            .collect();
+
+        // A write lands at the end of its step, so a read in the same step still needs the declared value
+        let first_read = reads.iter().copied().min();
+        let first_write = writes.iter().copied().min();
+        let is_written_first = match (first_write, first_read) {
+            (Some(write), Some(read)) => write < read,
+            (Some(_), None) => true,
+            _ => false,
+        };
+
+        let start = if is_written_first {
+            first_write.unwrap()
+        } else {
+            node.step
+        };
+        let end = reads.into_iter().chain(writes).fold(start, usize::max);
+        register_ranges.push((i, LiveRange { start, end }));
+        needs_value.push((i, !is_written_first));
+    }
+    let needs_value: HashMap<usize, bool> = needs_value.into_iter().collect();
+
+    let mut removed = HashSet::new();
+    let mut muxes = Vec::new();
+    for (_, ranges) in kinds {
+        for unit in bind(ranges, MAX_SHARED, |_| true) {
+            let members: Vec<Node> = unit.iter().map(|&i| nodes[i].clone()).collect();
+            muxes.extend(share_operator(&members, nodes, edges));
+            removed.extend(members[1..].iter().map(|node| node.id.clone()));
+            allocation.operators += members.len() - 1;
+        }
+    }
+    allocation.muxes = muxes.len();
+
+    for unit in bind(register_ranges, usize::MAX, |i| !needs_value[&i]) {
+        let members: Vec<Node> = unit.iter().map(|&i| nodes[i].clone()).collect();
+        for guest in &members[1..] {
+            redirect(edges, guest, &members[0]);
+            removed.insert(guest.id.clone());
+        }
+        allocation.registers += members.len() - 1;
+    }
+
+    nodes.retain(|node| !removed.contains(&node.id));
+    nodes.extend(muxes);
+
+    allocation
+}
+
+/// Bind the values to structures with the left edge algorithm: in the order they start, each value goes to the
+/// first structure free by then and holding less than `capacity` values, or to a new one. Only the values allowed
+/// to join take the structure of another one. Returns the structures holding several values, each with the value
+/// keeping its node first
+fn bind(
+    mut ranges: Vec<(usize, LiveRange)>,
+    capacity: usize,
+    can_join: impl Fn(usize) -> bool,
+) -> Vec<Vec<usize>> {
+    ranges.sort_by_key(|&(i, range)| (range.start, i));
+
+    let mut units: Vec<(usize, Vec<usize>)> = Vec::new();
+    for (i, range) in ranges {
+        let free = units
+            .iter_mut()
+            .find(|(end, members)| *end < range.start && members.len() < capacity)
+            .filter(|_| can_join(i));
+        match free {
+            Some((end, members)) => {
+                *end = range.end;
+                members.push(i);
+            }
+            None => units.push((range.end, vec![i])),
+        }
+    }
+
+    units
+        .into_iter()
+        .filter(|(_, members)| members.len() > 1)
+        .map(|(_, members)| members)
+        .collect()
+}
+
+/// Move the computations of the other members to the structure of the first one. Each input gets a multiplexer
+/// taking the operands of all the members, each one selected by the control signal of the step of its member.
+/// Returns the multiplexers
+fn share_operator(members: &[Node], nodes: &mut Vec<Node>, edges: &mut Vec<Edge>) -> Vec<Node> {
+    let unit = &members[0];
+    let mut muxes = Vec::new();
+
+    for (input, port) in unit.inputs.iter().enumerate() {
+        let mut mux = Node::from(NodeType::Mux(members.len()));
+        mux.step = unit.step;
+
+        for (j, member) in members.iter().enumerate() {
+            let member_port = &member.inputs[input].id;
+            for edge in edges.iter_mut() {
+                if edge.to_node == member.id && &edge.to_port == member_port {
+                    edge.to_node = mux.id.clone();
+                    edge.to_port = mux.get_input_id(port.size, j);
+                }
+            }
+            connect_control(nodes, edges, member.step, &mux, members.len() + j);
+        }
+
+        edges.push(Edge {
+            from_node: mux.id.clone(),
+            from_port: mux.get_output_id(port.size, 0),
+            to_node: unit.id.clone(),
+            to_port: port.id.clone(),
+            shift: 0,
+            mask: u32::MAX,
+        });
+        muxes.push(mux);
+    }
+
+    for member in &members[1..] {
+        redirect(edges, member, unit);
+    }
+
+    muxes
+}
+
+/// Wire the ports of the node to the same ports of the other one instead
+pub(crate) fn redirect(edges: &mut [Edge], from: &Node, to: &Node) {
+    for edge in edges {
+        if edge.from_node == from.id {
+            let port = from
+                .outputs
+                .iter()
+                .position(|p| p.id == edge.from_port)
+                .unwrap();
+            edge.from_node = to.id.clone();
+            edge.from_port = to.outputs[port].id.clone();
+        }
+        if edge.to_node == from.id {
+            let port = from
+                .inputs
+                .iter()
+                .position(|p| p.id == edge.to_port)
+                .unwrap();
+            edge.to_node = to.id.clone();
+            edge.to_port = to.inputs[port].id.clone();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler::Compiler, parser::parser::parse};
+
+    fn shared(program: &str) -> (Vec<Node>, Vec<Edge>, Allocation) {
+        let (ast, errors) = parse(program);
+        assert!(errors.is_empty(), "{errors:?}");
+        let mut compiler = Compiler::new();
+        compiler.compile(ast);
+        assert!(compiler.errors.is_empty(), "{:?}", compiler.errors);
+
+        let allocation = share_structures(&mut compiler.nodes, &mut compiler.edges);
+        (compiler.nodes, compiler.edges, allocation)
+    }
+
+    fn count(nodes: &[Node], kind: impl Fn(&NodeType) -> bool) -> usize {
+        nodes.iter().filter(|node| kind(&node.node)).count()
+    }
+
+    #[test]
+    fn two_additions_share_one_adder() {
+        let (nodes, edges, allocation) = shared("let i = 0;\ni + 1;\ni + 2;\n");
+        assert_eq!((allocation.operators, allocation.muxes), (1, 2));
+        assert_eq!(
+            count(&nodes, |node| matches!(
+                node,
+                NodeType::Operator(Operator::Add)
+            )),
+            1
+        );
+
+        // Each multiplexer takes the operand of both additions, selected by the step of each one
+        for mux in nodes
+            .iter()
+            .filter(|node| matches!(node.node, NodeType::Mux(2)))
+        {
+            for input in &mux.inputs {
+                assert!(edges.iter().any(|edge| edge.to_port == input.id));
+            }
+            for (j, step) in [1, 2].into_iter().enumerate() {
+                let select = &mux.inputs[2 + j];
+                let sources: Vec<&Node> = edges
+                    .iter()
+                    .filter(|edge| edge.to_port == select.id)
+                    .map(|edge| nodes.iter().find(|node| node.id == edge.from_node).unwrap())
+                    .collect();
+                assert_eq!(sources.len(), 8);
+                assert!(sources
+                    .iter()
+                    .all(|node| matches!(node.node, NodeType::Wait) && node.step == step));
+            }
+        }
+    }
+
+    #[test]
+    fn multiplexers_take_at_most_four_inputs() {
+        let (nodes, _, allocation) =
+            shared("let i = 0;\ni + 1;\ni + 2;\ni + 3;\ni + 4;\ni + 5;\ni + 6;\n");
+        assert_eq!(allocation.operators, 4);
+        assert_eq!(
+            count(&nodes, |node| matches!(
+                node,
+                NodeType::Operator(Operator::Add)
+            )),
+            2
+        );
+        assert_eq!(count(&nodes, |node| matches!(node, NodeType::Mux(4))), 2);
+        assert_eq!(count(&nodes, |node| matches!(node, NodeType::Mux(2))), 2);
+    }
+}
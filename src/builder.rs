@@ -1,59 +1,127 @@
 use crate::{
-    minecraft::structures::{MinecraftStructureNbt, MinecraftStructureSignNbt, PALETTE_AIR_NAME, PALETTE_SIGN_NAME},
-    nodes::{Node, NodeType},
+    layout::LayoutConfig,
+    minecraft::structures::{
+        MinecraftStructureNbt, MinecraftStructureSignNbt, PALETTE_AIR_NAME, PALETTE_REPEATER_NAME,
+        PALETTE_SIGN_NAME,
+    },
+    nodes::{Edge, Node, NodeType},
+    placement::{place, Connection, Orientation},
     vectors::Vector3,
 };
 use std::collections::{HashMap, HashSet};
 
-/// Takes the program nodes and get the corresponding structures. Each structures will be placed in a grid patterns.
-pub fn build_nodes(nodes: Vec<Node>) -> (Vec<String>, HashMap<String, Vec<Vector3>>, HashSet<Vector3>) {
-    // Grid
-    let grid_width = 50;
-    let origin_y = 150; // For testing purposes. Will be customizable later
-    let margin = 10;
+/// Lowest and highest corners of the structure of each node, by node id
+pub type StructureBoxes = HashMap<String, (Vector3, Vector3)>;
 
-    // Current node position
-    let mut current_row_start_x = 0;
-    let mut current_row_start_z = 0;
-    let mut current_row_z = 0;
+/// Takes the program nodes and get the corresponding structures. Each structures will be placed in a grid patterns,
+/// arranged to keep the connected structures close to each other.
+pub fn build_nodes(
+    nodes: Vec<Node>,
+    edges: &[Edge],
+    layout: &LayoutConfig,
+) -> (
+    Vec<String>,
+    HashMap<String, Vec<Vector3>>,
+    HashSet<Vector3>,
+    StructureBoxes,
+) {
+    let structures_nbt: Vec<MinecraftStructureNbt> = nodes
+        .iter()
+        .map(|node| NodeType::get_nbt(node.node.clone()))
+        .collect();
+    let sizes: Vec<Vector3> = structures_nbt
+        .iter()
+        .map(|nbt| Vector3(nbt.size[0] as i32, nbt.size[1] as i32, nbt.size[2] as i32))
+        .collect();
+
+    // Place the structures from the wires between their ports
+    let mut relative_ports = HashMap::new();
+    for (node, structure_nbt) in nodes.iter().zip(&structures_nbt) {
+        let template = (Vector3(0, 0, 0), Orientation::default());
+        register_structure(
+            node,
+            structure_nbt,
+            template,
+            &mut HashSet::new(),
+            &mut relative_ports,
+        );
+    }
+    let index = |id: &str| nodes.iter().position(|node| node.id == id).unwrap();
+    let connections: Vec<Connection> = edges
+        .iter()
+        .map(|edge| Connection {
+            from: index(&edge.from_node),
+            to: index(&edge.to_node),
+            from_port: relative_ports[&edge.from_port][0],
+            to_port: relative_ports[&edge.to_port][0],
+            bits: relative_ports[&edge.from_port].len() as u32,
+        })
+        .collect();
+    let controls: Vec<bool> = nodes
+        .iter()
+        .map(|node| node.is_primary || matches!(node.node, NodeType::Start))
+        .collect();
+    let placements = place(&sizes, &controls, &connections, layout);
 
     let mut instructions = Vec::new();
     let mut ports_data = HashMap::new();
     let mut obstacles = HashSet::new();
+    let mut structures = StructureBoxes::new();
 
-    for node in nodes.clone() {
-        let structure_nbt = NodeType::get_nbt(node.node.clone());
-        let [x, _, z] = structure_nbt.size;
-
-        if current_row_z < z {
-            current_row_z = z;
-        }
-
-        // Grid cell coords
-        let (cell_x, cell_y, cell_z) = (current_row_start_x, origin_y, current_row_start_z);
+    for (i, node) in nodes.iter().enumerate() {
+        let (structure_nbt, (position, orientation)) = (&structures_nbt[i], placements[i]);
+        let Vector3(cell_x, cell_y, cell_z) = position;
+        let Vector3(x, y, z) = orientation.size(sizes[i]);
 
         register_structure(
-            &node,
-            &structure_nbt,
-            Vector3(cell_x, cell_y, cell_z),
+            node,
+            structure_nbt,
+            placements[i],
             &mut obstacles,
             &mut ports_data,
         );
 
+        let corner = Vector3(cell_x + x - 1, cell_y + y - 1, cell_z + z - 1);
+        structures.insert(node.id.clone(), (position, corner));
+
         // Place the structure
-        let name = NodeType::get_name(node.clone().node);
+        let name = NodeType::get_name(node.node.clone());
         instructions.push(format!(
-            "place template redsharp:{name} {cell_x} {cell_y} {cell_z}"
+            "place template redsharp:{name} {}",
+            orientation.place_arguments(position, sizes[i])
         ));
 
-        // Move to the next cell
-        current_row_start_x += (x + margin) as i32;
+        // The int structure starts with its value, the constant or the initial value of the variable
+        if let NodeType::Int(value) | NodeType::Register { value, .. } = node.node {
+            instructions.extend(store_value(value, structure_nbt, placements[i]));
+        }
+
+        // Write the doc comment on a sign standing on top of the structure
+        if let Some(doc) = &node.doc {
+            if !fits_on_sign(doc) {
+                eprintln!(
+                    "Warning: The doc comment {doc:?} does not fit on the 4 lines of {} characters of its sign, it is cut",
+                    SIGN_LINE_WIDTH
+                );
+            }
+
+            let support = Vector3(cell_x, cell_y + y, cell_z);
+            let sign = Vector3(support.0, support.1 + 1, support.2);
+
+            instructions.push(format!(
+                "setblock {} {} {} minecraft:orange_wool",
+                support.0, support.1, support.2
+            ));
+            instructions.push(format!(
+                "setblock {} {} {} minecraft:oak_sign{{front_text:{{messages:[{}]}}}}",
+                sign.0,
+                sign.1,
+                sign.2,
+                sign_messages(doc).join(",")
+            ));
 
-        // Go to the next row if too far
-        if current_row_start_x >= grid_width {
-            current_row_start_x = 0;
-            current_row_start_z += (current_row_z + margin) as i32;
-            current_row_z = 0;
+            obstacles.insert(support);
+            obstacles.insert(sign);
         }
     }
 
@@ -65,14 +133,93 @@ pub fn build_nodes(nodes: Vec<Node>) -> (Vec<String>, HashMap<String, Vec<Vector
         }
     }
 
-    (instructions, ports_data, obstacles)
+    (instructions, ports_data, obstacles, structures)
+}
+
+/// Roughly the number of characters fitting on a sign line
+const SIGN_LINE_WIDTH: usize = 15;
+
+/// Wraps the text into lines fitting the width of a sign
+fn sign_lines(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for word in text.split_whitespace() {
+        match lines.last_mut() {
+            Some(line) if line.len() + 1 + word.len() <= SIGN_LINE_WIDTH => {
+                line.push(' ');
+                line.push_str(word);
+            }
+            _ => lines.push(word.to_string()),
+        }
+    }
+    lines
+}
+
+/// Whether the text is shown whole on a sign: it wraps into 4 lines, without a word longer than a line
+pub fn fits_on_sign(text: &str) -> bool {
+    let lines = sign_lines(text);
+    lines.len() <= 4 && lines.iter().all(|line| line.len() <= SIGN_LINE_WIDTH)
+}
+
+/// Wraps the text into the 4 lines of a sign, as SNBT strings holding JSON text components. The rest is cut
+pub fn sign_messages(text: &str) -> [String; 4] {
+    let lines = sign_lines(text);
+    std::array::from_fn(|i| {
+        let line = lines.get(i).map(String::as_str).unwrap_or("");
+        let json = serde_json::to_string(line).unwrap();
+        format!("'{}'", json.replace('\\', "\\\\").replace('\'', "\\'"))
+    })
+}
+
+/// Power the locked repeaters storing the bits of the value in the int structure, the lowest bit being the lowest
+/// repeater. The commands come after the structure is placed, and the blocks the repeaters drive are updated
+fn store_value(
+    value: u32,
+    structure_nbt: &MinecraftStructureNbt,
+    (origin, orientation): (Vector3, Orientation),
+) -> Vec<String> {
+    let size = Vector3(
+        structure_nbt.size[0] as i32,
+        structure_nbt.size[1] as i32,
+        structure_nbt.size[2] as i32,
+    );
+    let mut bits: Vec<(Vector3, &HashMap<String, String>)> = structure_nbt
+        .blocks
+        .iter()
+        .filter_map(|block| {
+            let state = &structure_nbt.palette[block.state as usize];
+            let properties = state.properties.as_ref()?;
+            (state.name == PALETTE_REPEATER_NAME && properties.get("locked")? == "true").then(
+                || {
+                    (
+                        Vector3(block.pos[0], block.pos[1], block.pos[2]),
+                        properties,
+                    )
+                },
+            )
+        })
+        .collect();
+    bits.sort_by_key(|(pos, _)| pos.1);
+
+    bits.into_iter()
+        .enumerate()
+        .filter(|&(bit, _)| value >> bit & 1 == 1)
+        .map(|(_, (pos, properties))| {
+            let Vector3(x, y, z) = origin + orientation.apply(pos, size);
+            format!(
+                "setblock {x} {y} {z} {PALETTE_REPEATER_NAME}[facing={},delay={},locked=true,powered=true]",
+                orientation.facing(&properties["facing"]),
+                properties["delay"]
+            )
+        })
+        .collect()
 }
 
-/// Use the structure NBT to get the ports positions, and register all other blocks as obstacles
+/// Use the structure NBT to get the ports positions, and register all other blocks as obstacles. The structure is
+/// placed with its lowest corner at the origin, turned by the orientation
 fn register_structure(
     node: &Node,
     structure_nbt: &MinecraftStructureNbt,
-    origin: Vector3,
+    (origin, orientation): (Vector3, Orientation),
     obstacles: &mut HashSet<Vector3>,
     ports_data: &mut HashMap<String, Vec<Vector3>>,
 ) {
@@ -81,7 +228,7 @@ fn register_structure(
         .palette
         .iter()
         .enumerate()
-        .filter_map(|(index, &ref value)| {
+        .filter_map(|(index, value)| {
             if value.name == PALETTE_SIGN_NAME {
                 Some(index)
             } else {
@@ -95,20 +242,22 @@ fn register_structure(
         .palette
         .iter()
         .enumerate()
-        .find(|(_, &ref value)| value.name == PALETTE_AIR_NAME)
+        .find(|(_, value)| value.name == PALETTE_AIR_NAME)
         .unwrap()
         .0;
 
+    let size = Vector3(
+        structure_nbt.size[0] as i32,
+        structure_nbt.size[1] as i32,
+        structure_nbt.size[2] as i32,
+    );
     for block in &structure_nbt.blocks {
         if block.state as usize == air_state {
             continue;
         }
 
-        let block_pos = Vector3(
-            origin.0 + block.pos[0],
-            origin.1 + block.pos[1],
-            origin.2 + block.pos[2],
-        );
+        let block_pos =
+            origin + orientation.apply(Vector3(block.pos[0], block.pos[1], block.pos[2]), size);
 
         if sign_states.contains(&(block.state as usize)) {
             if let Some(block_nbt) = &block.nbt {
@@ -136,7 +285,10 @@ fn register_structure(
                         .or_insert_with(|| vec![Vector3(0, 0, 0); port.size as usize]);
                     val[id] = block_pos;
                 } else {
-                    panic!("A sign string is malformed. Got {sign_data:?} at {block_pos:?} in {:?}", node.node);
+                    panic!(
+                        "A sign string is malformed. Got {sign_data:?} at {block_pos:?} in {:?}",
+                        node.node
+                    );
                 }
             } else {
                 obstacles.insert(block_pos);
@@ -146,3 +298,48 @@ fn register_structure(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{minecraft::structures::read_minecraft_structure_file, placement::Rotation};
+
+    #[test]
+    fn sign_text_is_wrapped_and_cut() {
+        assert!(fits_on_sign("The counter of the main loop"));
+        assert_eq!(
+            sign_messages("The counter of the main loop")[1],
+            r#"'"the main loop"'"#
+        );
+
+        assert!(!fits_on_sign(
+            "one two three four five six seven eight nine ten eleven twelve"
+        ));
+        assert!(!fits_on_sign("A_word_longer_than_a_line"));
+    }
+
+    #[test]
+    fn values_are_stored_in_the_locked_repeaters() {
+        let int = read_minecraft_structure_file("redsharp/data/redsharp/structure/int.nbt");
+
+        let placed = (Vector3(10, 0, 0), Orientation::default());
+        assert_eq!(
+            store_value(5, &int, placed),
+            [
+                "setblock 13 1 8 minecraft:repeater[facing=north,delay=1,locked=true,powered=true]",
+                "setblock 13 5 8 minecraft:repeater[facing=north,delay=1,locked=true,powered=true]",
+            ]
+        );
+        assert!(store_value(0, &int, placed).is_empty());
+        assert_eq!(store_value(0xFF, &int, placed).len(), 8);
+
+        let turned = Orientation {
+            rotation: Rotation::Clockwise90,
+            mirror: false,
+        };
+        assert_eq!(
+            store_value(1 << 7, &int, (Vector3(0, 0, 0), turned)),
+            ["setblock 4 15 3 minecraft:repeater[facing=east,delay=1,locked=true,powered=true]"]
+        );
+    }
+}
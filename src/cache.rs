@@ -0,0 +1,169 @@
+use crate::{pathfinding::Wire, router::RouterKind, vectors::Vector3};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fs, io};
+
+/// File the routed wires are kept in between the builds
+const CACHE_FILE: &str = "redsharp.cache.json";
+
+/// The wires of the last build, to only route again the ones that changed
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct RoutingCache {
+    /// Hash of the layout the wires were routed in: the router, the blocks of the structures, the routing volume and
+    /// the ends of the wires
+    layout_hash: u64,
+    /// The wires of each output port bit, each one after the first branching off the ones before it
+    trees: Vec<Vec<Wire>>,
+}
+
+impl RoutingCache {
+    /// Read the cache of the last build. Without one, or when it cannot be read, every wire is routed again
+    pub fn load() -> Self {
+        let Ok(content) = fs::read_to_string(CACHE_FILE) else {
+            return Self::default();
+        };
+
+        serde_json::from_str(&content).unwrap_or_else(|error| {
+            eprintln!("Ignoring the routing cache, it could not be read: {error}");
+            Self::default()
+        })
+    }
+
+    pub fn save(&self) -> Result<(), io::Error> {
+        fs::write(CACHE_FILE, serde_json::to_string(self)?)
+    }
+
+    /// Whether the wires were routed in this same layout, so they can all be used as they are
+    pub fn is_layout_unchanged(&self, layout_hash: u64) -> bool {
+        !self.trees.is_empty() && self.layout_hash == layout_hash
+    }
+
+    /// The cached wires going from the start to each of the goals, in the same order
+    pub fn tree(&self, start: Vector3, goals: &[Vector3]) -> Option<&[Wire]> {
+        self.trees
+            .iter()
+            .find(|wires| {
+                wires.len() == goals.len()
+                    && wires[0].path.first() == Some(&start)
+                    && wires
+                        .iter()
+                        .zip(goals)
+                        .all(|(wire, goal)| wire.path.last() == Some(goal))
+            })
+            .map(Vec::as_slice)
+    }
+
+    /// Replace the cached wires with the ones routed in the layout
+    pub fn update(&mut self, layout_hash: u64, trees: Vec<Vec<Wire>>) {
+        self.layout_hash = layout_hash;
+        self.trees = trees;
+    }
+}
+
+/// Hash of the layout the wires are routed in, the same from one build to the next when the router, the structures
+/// and the ports do not change. The numbers are hashed as little-endian bytes, the lists after their length
+pub fn layout_hash(
+    router: RouterKind,
+    obstacles: &HashSet<Vector3>,
+    bounds: (Vector3, Vector3),
+    ends: &[(Vector3, Vector3)],
+) -> u64 {
+    let mut obstacles: Vec<&Vector3> = obstacles.iter().collect();
+    obstacles.sort_by_key(|pos| (pos.0, pos.1, pos.2));
+
+    let mut hasher = FnvHasher::default();
+    hasher.write(&[match router {
+        RouterKind::AStar => 0,
+        RouterKind::Lee => 1,
+        RouterKind::StraightRuns => 2,
+    }]);
+    hasher.write(&(obstacles.len() as u64).to_le_bytes());
+    for &&pos in &obstacles {
+        hasher.write_position(pos);
+    }
+    hasher.write_position(bounds.0);
+    hasher.write_position(bounds.1);
+    hasher.write(&(ends.len() as u64).to_le_bytes());
+    for &(start, goal) in ends {
+        hasher.write_position(start);
+        hasher.write_position(goal);
+    }
+    hasher.finish()
+}
+
+/// 64-bit FNV-1a hash. The hash is written to the cache file, so it cannot change with the Rust version or the
+/// platform like the hasher and the `Hash` implementations of the standard library
+struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+
+    fn write_position(&mut self, Vector3(x, y, z): Vector3) {
+        for coordinate in [x, y, z] {
+            self.write(&coordinate.to_le_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redstone::WireBlock;
+
+    fn wire(path: &[Vector3]) -> Wire {
+        Wire {
+            path: path.to_vec(),
+            blocks: vec![WireBlock::Redstone; path.len()],
+        }
+    }
+
+    #[test]
+    fn layout_hash_is_fixed_and_depends_on_the_router() {
+        let obstacles = HashSet::from([Vector3(1, 2, 3), Vector3(4, 5, 6)]);
+        let bounds = (Vector3(0, 0, 0), Vector3(10, 10, 10));
+        let ends = [(Vector3(1, 0, 0), Vector3(9, 0, 0))];
+        let hash = |router, ends: &[_]| layout_hash(router, &obstacles, bounds, ends);
+
+        let mut hasher = FnvHasher::default();
+        hasher.write(b"a");
+        assert_eq!(hasher.finish(), 0xaf63_dc4c_8601_ec8c);
+        let a_star = hash(RouterKind::AStar, &ends);
+        assert_eq!(a_star, 0x376a_8643_e305_5aa9);
+        assert_ne!(a_star, hash(RouterKind::Lee, &ends));
+        assert_ne!(a_star, hash(RouterKind::AStar, &[]));
+    }
+
+    #[test]
+    fn cached_trees_are_found_by_their_ends() {
+        let (start, a, b) = (Vector3(0, 0, 0), Vector3(2, 0, 0), Vector3(0, 0, 2));
+        let mut cache = RoutingCache::default();
+        assert!(!cache.is_layout_unchanged(0));
+
+        cache.update(
+            7,
+            vec![vec![
+                wire(&[start, Vector3(1, 0, 0), a]),
+                wire(&[start, Vector3(0, 0, 1), b]),
+            ]],
+        );
+        assert!(cache.is_layout_unchanged(7));
+        assert!(!cache.is_layout_unchanged(8));
+        assert_eq!(cache.tree(start, &[a, b]).map(<[Wire]>::len), Some(2));
+        assert!(cache.tree(start, &[b, a]).is_none());
+        assert!(cache.tree(a, &[b]).is_none());
+    }
+}
@@ -1,11 +1,58 @@
-use super::nodes::{Node, NodeType};
-use crate::parser::ast::{Expr, Operator, Statement};
+use super::nodes::{Edge, Node, NodeType};
+use crate::{
+    control::connect_control,
+    parser::{
+        ast::{Expr, Operator, Statement},
+        formatter::print_expr,
+    },
+};
+
+/// Size in bits of the registers and of the buses between the structures
+const WORD_SIZE: u32 = 8;
+
+/// The output bus an expression must be wired from
+#[derive(Clone)]
+struct Operand {
+    node_id: String,
+    port_id: String,
+    shift: i32,
+    /// The bits of the output still in the word after each of the shifts
+    mask: u32,
+}
+
+impl Operand {
+    fn from(node: &Node) -> Self {
+        Self {
+            node_id: node.id.clone(),
+            port_id: node.get_output_id(8, 0),
+            shift: 0,
+            mask: u32::MAX,
+        }
+    }
+}
+
+/// A function declared by the program. Its body is compiled again at each call
+struct Function {
+    name: String,
+    parameters: Vec<String>,
+    body: Vec<Statement>,
+    doc: Option<String>,
+}
 
 /// Compile the given AST into nodes
 pub struct Compiler {
     pub nodes: Vec<Node>,
-    /// (node1_id, port1_id, node2_id, port2_id)
-    pub edges: Vec<(String, String, String, String)>,
+    pub edges: Vec<Edge>,
+    /// The errors of the program, the parts of it with an error are not compiled
+    pub errors: Vec<String>,
+    /// Index of the next statement to compile
+    step: usize,
+    functions: Vec<Function>,
+    /// The calls being compiled, with the operands their parameters are bound to
+    calls: Vec<(String, Vec<(String, Operand)>)>,
+    /// The variables visible, with the id of their register. A variable declared in a block is visible until the end
+    /// of the block, and hides the ones declared before it with the same name
+    variables: Vec<(String, String)>,
 }
 
 impl Compiler {
@@ -13,168 +60,674 @@ impl Compiler {
         Self {
             nodes: Vec::new(),
             edges: Vec::new(),
+            errors: Vec::new(),
+            step: 0,
+            functions: Vec::new(),
+            calls: Vec::new(),
+            variables: Vec::new(),
         }
     }
 
-    fn get_variable_structure(&self, var_name: String) -> Option<&Node> {
-        for s in &self.nodes {
-            if let NodeType::VarInt { name, .. } = &s.node {
-                if name.eq(&var_name) {
-                    return Some(s);
-                }
-            }
-        }
-        None
+    /// The register of the variable, the last one visible with the name
+    fn get_register(&self, var_name: &str) -> Option<&Node> {
+        let (_, id) = self
+            .variables
+            .iter()
+            .rev()
+            .find(|(name, _)| name == var_name)?;
+        self.nodes.iter().find(|node| &node.id == id)
     }
 
     pub fn compile(&mut self, statements: Vec<Statement>) {
         for statement in statements {
             self.compile_statement(statement);
         }
+
+        // Each write runs with the control signal of its step
+        let sets: Vec<Node> = self
+            .nodes
+            .iter()
+            .filter(|node| matches!(node.node, NodeType::Set))
+            .cloned()
+            .collect();
+        for set in sets {
+            connect_control(&mut self.nodes, &mut self.edges, set.step, &set, 0);
+        }
+    }
+
+    /// Compile the statements of a block, the variables declared in it are only visible there
+    fn compile_block(&mut self, statements: Vec<Statement>) {
+        let visible = self.variables.len();
+        for statement in statements {
+            self.compile_statement(statement);
+        }
+        self.variables.truncate(visible);
     }
 
     fn compile_statement(&mut self, statement: Statement) {
+        if let Statement::Block(block) = statement {
+            self.compile_block(block);
+            return;
+        }
+
+        // The nodes of the statement run in its step
+        let first = self.nodes.len();
         match statement {
+            // Reading a variable alone does nothing, its register is already there
+            Statement::Expression(Expr::Identifier(name)) => {
+                let is_parameter = self.calls.last().is_some_and(|(_, parameters)| {
+                    parameters.iter().any(|(parameter, _)| *parameter == name)
+                });
+                if !is_parameter && self.get_register(&name).is_none() {
+                    self.errors.push(format!("Unknown variable `{name}`"));
+                }
+            }
+            // A shift only moves the bits along the wires, there is no node to leave its result on
+            Statement::Expression(
+                expr @ Expr::Infix {
+                    operator: Operator::ShiftLeft | Operator::ShiftRight,
+                    ..
+                },
+            ) => {
+                self.errors.push(format!(
+                    "The result of the shift `{}` must be used by an operation or an assignment",
+                    print_expr(&expr)
+                ));
+            }
+            Statement::Expression(Expr::Function {
+                name,
+                parameters,
+                body,
+                doc,
+            }) => {
+                if name.is_empty() {
+                    self.errors
+                        .push("A function must have a name to be called".to_string());
+                }
+                self.functions.push(Function {
+                    name,
+                    parameters,
+                    body,
+                    doc,
+                });
+            }
+            Statement::Expression(Expr::Call { left, arguments }) => {
+                self.compile_call(*left, arguments);
+            }
             Statement::Expression(expr) => {
                 let node = self.compile_expression(expr);
                 self.nodes.push(node);
             }
-            Statement::Let(name, expr) => {
-                self.compile_let(name, expr);
-            }
-            Statement::Block(block) => {
-                self.compile(block);
+            Statement::Let(name, expr, doc) => {
+                self.compile_let(name, expr, doc);
             }
             _ => unimplemented!("Statement type ({statement:?})"),
         };
+
+        for node in &mut self.nodes[first..] {
+            node.step = self.step;
+        }
+        self.step += 1;
+    }
+
+    /// Declare a variable. A typed literal gives the variable its size, like `let i = 5u4;` declaring a 4 bits variable
+    /// and is its initial value. Any other value is written to the variable in the step of the declaration, like an
+    /// assignment
+    fn compile_let(&mut self, name: String, expr: Expr, doc: Option<String>) {
+        let (value, width) = match expr {
+            Expr::Int { value, width, .. } => {
+                self.check_width(&expr, WORD_SIZE, "the registers");
+                (value, width.unwrap_or(WORD_SIZE))
+            }
+            _ => (0, WORD_SIZE),
+        };
+
+        let mut register = Node::from(NodeType::Register {
+            name: name.clone(),
+            value,
+            width,
+        });
+        register.doc = doc;
+
+        // The value is computed before the variable is visible, so it reads the variables it hides
+        if !matches!(expr, Expr::Int { .. }) {
+            let set_node = self.compile_write(Some(&register), expr);
+            self.nodes.push(set_node);
+        }
+        self.variables.push((name, register.id.clone()));
+        self.nodes.push(register);
+    }
+
+    /// Create the write of the value, to the register if there is one. The write drives the data and the write enable
+    /// of the register
+    fn compile_write(&mut self, register: Option<&Node>, value: Expr) -> Node {
+        if let Some(NodeType::Register { name, width, .. }) =
+            register.map(|register| &register.node)
+        {
+            self.check_width(&value, *width, &format!("`{name}`"));
+        }
+
+        let set_node = Node::from(NodeType::Set);
+        let rhs = self.compile_operand(value);
+        self.connect(&rhs, &set_node, 1);
+
+        if let Some(register) = register {
+            self.connect_port(
+                &set_node,
+                set_node.get_output_id(8, 0),
+                register,
+                register.get_input_id(8, 0),
+            );
+            self.connect_port(
+                &set_node,
+                set_node.get_output_id(1, 0),
+                register,
+                register.get_input_id(1, 0),
+            );
+        }
+
+        set_node
     }
 
-    fn compile_let(&mut self, name: String, expr: Expr) {
-        if let Expr::Int { value } = expr {
-            let s = Node::from(NodeType::VarInt {
-                name: name.clone(),
-                value: value.clone(),
+    /// Compile an operand and get the output it must be wired from.
+    /// Variables are read from the output of their register and constant shifts only move the bits along the wires,
+    /// so neither creates a node
+    fn compile_operand(&mut self, expr: Expr) -> Operand {
+        if let Expr::Identifier(name) = &expr {
+            let parameter = self.calls.last().and_then(|(_, parameters)| {
+                parameters.iter().find(|(parameter, _)| parameter == name)
             });
+            if let Some((_, operand)) = parameter {
+                return operand.clone();
+            }
+
+            match self.get_register(name) {
+                Some(register) => return Operand::from(register),
+                None => {
+                    self.errors.push(format!("Unknown variable `{name}`"));
+                    return self.compile_operand(Expr::Int {
+                        value: 0,
+                        width: None,
+                        text: "0".to_string(),
+                    });
+                }
+            }
+        }
+
+        if let Expr::Infix {
+            left,
+            operator: operator @ (Operator::ShiftLeft | Operator::ShiftRight),
+            right,
+        } = expr
+        {
+            let amount = match *right {
+                Expr::Int { value, .. } => value as i32,
+                _ => {
+                    self.errors.push(format!(
+                        "Shifts are only supported by a constant amount, but got `{}`",
+                        print_expr(&right)
+                    ));
+                    0
+                }
+            };
+
+            let mut operand = self.compile_operand(*left);
+            operand.shift += if operator == Operator::ShiftLeft {
+                amount
+            } else {
+                -amount
+            };
+            // The bits shifted out of the word are lost, a shift the other way brings back zeros
+            let shift = operand.shift;
+            operand.mask &= (0..WORD_SIZE as i32)
+                .filter(|bit| (0..WORD_SIZE as i32).contains(&(bit + shift)))
+                .fold(0, |mask, bit| mask | 1 << bit);
+            return operand;
+        }
 
-            self.nodes.push(s);
-        } else {
-            panic!("Invalid declare value");
+        if let Expr::Call { left, arguments } = expr {
+            return match self.compile_call(*left, arguments) {
+                Some(operand) => operand,
+                None => self.compile_operand(Expr::Int {
+                    value: 0,
+                    width: None,
+                    text: "0".to_string(),
+                }),
+            };
         }
+
+        let node = self.compile_expression(expr);
+        let operand = Operand::from(&node);
+        self.nodes.push(node);
+        operand
     }
 
-    fn parse_identifier(&self, expr: Expr) -> Option<&Node> {
-        if let Expr::Identifier(name) = expr {
-            if let Some(s) = self.get_variable_structure(name.into()) {
-                return Some(s);
+    /// Compile a function call by compiling the body of the function in place, with its parameters bound to the
+    /// arguments. The result is the value returned, or the last expression of the body. The doc comment of the
+    /// function is written on the structure computing it. Returns None for a call with an error or without result
+    fn compile_call(&mut self, left: Expr, arguments: Vec<Expr>) -> Option<Operand> {
+        let name = match left {
+            Expr::Identifier(name) => name,
+            left => {
+                self.errors.push(format!(
+                    "Only a function declared by its name can be called, but got `{}`",
+                    print_expr(&left)
+                ));
+                return None;
+            }
+        };
+
+        let Some(function) = self.functions.iter().rev().find(|f| f.name == name) else {
+            self.errors.push(format!("Unknown function `{name}`"));
+            return None;
+        };
+        if function.parameters.len() != arguments.len() {
+            self.errors.push(format!(
+                "The function `{name}` takes {} argument(s), but got {}",
+                function.parameters.len(),
+                arguments.len()
+            ));
+            return None;
+        }
+        if self.calls.iter().any(|(call, _)| *call == name) {
+            self.errors.push(format!(
+                "The function `{name}` calls itself, but the calls are built in place so they cannot be recursive"
+            ));
+            return None;
+        }
+        let (parameters, mut body, doc) = (
+            function.parameters.clone(),
+            function.body.clone(),
+            function.doc.clone(),
+        );
+
+        let operands = arguments
+            .into_iter()
+            .map(|argument| self.compile_operand(argument));
+        let bound = parameters.into_iter().zip(operands).collect();
+        self.calls.push((name, bound));
+
+        let first = self.nodes.len();
+        let result = match body.pop() {
+            Some(Statement::Return(expr) | Statement::Expression(expr))
+                if !matches!(expr, Expr::Assignment { .. }) =>
+            {
+                self.compile_block(body);
+                Some(self.compile_operand(expr))
+            }
+            last => {
+                self.compile_block(body.into_iter().chain(last).collect());
+                None
+            }
+        };
+        self.calls.pop();
+
+        // A result wired from an argument or a variable has no structure of its own to document
+        let result = result?;
+        let computed = self.nodes[first..]
+            .iter_mut()
+            .find(|node| node.id == result.node_id);
+        if let Some(node) = computed {
+            if node.doc.is_none() {
+                node.doc = doc;
             }
         }
+        Some(result)
+    }
+
+    /// Check an integer literal fits the bits it is stored in: its type must not be wider, and its value must fit
+    fn check_width(&mut self, expr: &Expr, size: u32, target: &str) {
+        let &Expr::Int { value, width, .. } = expr else {
+            return;
+        };
+
+        if width.is_some_and(|width| width > size) {
+            self.errors.push(format!(
+                "The integer literal `{}` is wider than {target} ({size} bits)",
+                print_expr(expr)
+            ));
+        } else if value as u64 >= 1 << size {
+            self.errors.push(format!(
+                "The integer literal `{}` does not fit in {target} ({size} bits)",
+                print_expr(expr)
+            ));
+        }
+    }
 
-        None
+    /// Wire the operand to the nth 8 bits input of the node
+    fn connect(&mut self, operand: &Operand, node: &Node, index: usize) {
+        self.edges.push(Edge {
+            from_node: operand.node_id.clone(),
+            from_port: operand.port_id.clone(),
+            to_node: node.id.clone(),
+            to_port: node.get_input_id(8, index),
+            shift: operand.shift,
+            mask: operand.mask,
+        });
+    }
+
+    /// Wire an output port of a node to an input port of another one
+    fn connect_port(&mut self, from: &Node, from_port: String, to: &Node, to_port: String) {
+        self.edges.push(Edge {
+            from_node: from.id.clone(),
+            from_port,
+            to_node: to.id.clone(),
+            to_port,
+            shift: 0,
+            mask: u32::MAX,
+        });
+    }
+
+    /// Create an operator node with both operands wired to its inputs
+    fn compile_binary(&mut self, operator: Operator, lhs: &Operand, rhs: &Operand) -> Node {
+        let op_node = Node::from(NodeType::Operator(operator));
+        self.connect(lhs, &op_node, 0);
+        self.connect(rhs, &op_node, 1);
+        op_node
     }
 
     pub fn compile_expression(&mut self, node: Expr) -> Node {
         match node {
             Expr::Identifier(name) => {
-                if let Some(_) = self.get_variable_structure(name.clone().into()) {
-                    return Node::from(NodeType::IntRef).into();
-                } else {
-                    panic!("Invalid variable ({name})");
-                }
+                panic!("A variable is read by the operation or the assignment using it ({name})")
             }
-            Expr::Int { value } => {
-                let s = Node::from(NodeType::Int(value.clone()));
-                return s.into();
+            Expr::Int { value, .. } => {
+                self.check_width(&node, WORD_SIZE, "the int structure");
+                Node::from(NodeType::Int(value))
             }
+            Expr::Prefix { operator, right } => match operator {
+                Operator::BitNot => {
+                    let operand = self.compile_operand(*right);
+                    let op_node = Node::from(NodeType::Operator(operator));
+                    self.connect(&operand, &op_node, 0);
+                    op_node
+                }
+                _ => unimplemented!("Prefix operator ({operator:?})"),
+            },
             Expr::Infix {
                 left,
                 operator,
                 right,
             } => match operator {
-                Operator::Add => {
-                    let op_node = Node::from(NodeType::Operator(operator.clone()));
-
-                    if let Some(lhs_node) = self.parse_identifier(*left.clone()) {
-                        self.edges.push((
-                            lhs_node.id.clone(),
-                            lhs_node.get_output_id(8, 0),
-                            op_node.id.clone(),
-                            op_node.get_input_id(8, 0),
-                        ));
-                    } else {
-                        let lhs_node = self.compile_expression(*left);
-                        self.edges.push((
-                            lhs_node.id.clone(),
-                            lhs_node.get_output_id(8, 0),
-                            op_node.id.clone(),
-                            op_node.get_input_id(8, 0),
-                        ));
-                        self.nodes.push(lhs_node);
-                    }
+                Operator::Add | Operator::BitAnd | Operator::BitOr => {
+                    let lhs = self.compile_operand(*left);
+                    let rhs = self.compile_operand(*right);
+                    self.compile_binary(operator, &lhs, &rhs)
+                }
+                Operator::BitXor => {
+                    // There is no XOR structure, it is built from the other gates: a ~ b = (a | b) & ~(a & b)
+                    let lhs = self.compile_operand(*left);
+                    let rhs = self.compile_operand(*right);
 
-                    if let Some(rhs_node) = self.parse_identifier(*right.clone()) {
-                        self.edges.push((
-                            rhs_node.id.clone(),
-                            rhs_node.get_output_id(8, 0),
-                            op_node.id.clone(),
-                            op_node.get_input_id(8, 1),
-                        ));
-                    } else {
-                        let rhs_node = self.compile_expression(*right);
-                        self.edges.push((
-                            rhs_node.id.clone(),
-                            rhs_node.get_output_id(8, 0),
-                            op_node.id.clone(),
-                            op_node.get_input_id(8, 1),
-                        ));
-                        self.nodes.push(rhs_node);
-                    }
+                    let or_node = self.compile_binary(Operator::BitOr, &lhs, &rhs);
+                    let and_node = self.compile_binary(Operator::BitAnd, &lhs, &rhs);
+                    let not_node = Node::from(NodeType::Operator(Operator::BitNot));
+                    self.connect(&Operand::from(&and_node), &not_node, 0);
 
-                    op_node
+                    let xor_node = self.compile_binary(
+                        Operator::BitAnd,
+                        &Operand::from(&or_node),
+                        &Operand::from(&not_node),
+                    );
+
+                    self.nodes.push(or_node);
+                    self.nodes.push(and_node);
+                    self.nodes.push(not_node);
+                    xor_node
+                }
+                Operator::ShiftLeft | Operator::ShiftRight => {
+                    unreachable!("The shifts are compiled as operands, or rejected as statements")
                 }
                 _ => unimplemented!("Infix operator ({operator:?})"),
             },
             Expr::Assignment { left, right } => {
-                let set_node = Node::from(NodeType::Set);
-
-                if let Some(rhs_node) = self.parse_identifier(*right.clone()) {
-                    self.edges.push((
-                        rhs_node.id.clone(),
-                        rhs_node.get_output_id(8, 0),
-                        set_node.id.clone(),
-                        set_node.get_input_id(8, 0),
-                    ));
-                } else {
-                    let rhs_node = self.compile_expression(*right);
-                    self.edges.push((
-                        rhs_node.id.clone(),
-                        rhs_node.get_output_id(8, 0),
-                        set_node.id.clone(),
-                        set_node.get_input_id(8, 0),
-                    ));
-                    self.nodes.push(rhs_node);
-                }
-
-                if let Some(lhs_node) = self.parse_identifier(*left.clone()) {
-                    self.edges.push((
-                        set_node.id.clone(),
-                        set_node.get_output_id(8, 0),
-                        lhs_node.id.clone(),
-                        lhs_node.get_input_id(8, 0),
-                    ));
-                } else {
-                    let lhs_node = self.compile_expression(*left);
-                    self.edges.push((
-                        set_node.id.clone(),
-                        set_node.get_output_id(8, 0),
-                        lhs_node.id.clone(),
-                        lhs_node.get_input_id(8, 0),
-                    ));
-                    self.nodes.push(lhs_node);
-                }
-
-                set_node
+                let register = match *left {
+                    Expr::Identifier(name) => {
+                        let register = self.get_register(&name).cloned();
+                        if register.is_none() {
+                            self.errors.push(format!("Unknown variable `{name}`"));
+                        }
+                        register
+                    }
+                    left => {
+                        self.errors.push(format!(
+                            "Only a variable can be assigned, but got `{}`",
+                            print_expr(&left)
+                        ));
+                        None
+                    }
+                };
+                self.compile_write(register.as_ref(), *right)
             }
             _ => unimplemented!("Node type ({node:?})"),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser::parse;
+
+    fn compile(program: &str) -> Compiler {
+        let (ast, errors) = parse(program);
+        assert!(errors.is_empty(), "{errors:?}");
+
+        let mut compiler = Compiler::new();
+        compiler.compile(ast);
+        compiler
+    }
+
+    #[test]
+    fn constant_shifts_are_rewiring() {
+        let compiler = compile("let i = 1;\nlet j = 2;\ni = j << 2;\n");
+        assert!(compiler.errors.is_empty(), "{:?}", compiler.errors);
+        assert!(compiler.edges.iter().any(|edge| edge.shift == 2));
+    }
+
+    #[test]
+    fn bits_shifted_out_stay_cleared() {
+        let compiler = compile("let i = 1;\nlet j = 2;\ni = (j >> 2) << 2;\ni = (j << 6) >> 5;\n");
+        assert!(compiler.errors.is_empty(), "{:?}", compiler.errors);
+
+        let j = compiler.get_register("j").unwrap();
+        let reads: Vec<(i32, u32)> = compiler
+            .edges
+            .iter()
+            .filter(|edge| edge.from_node == j.id)
+            .map(|edge| (edge.shift, edge.mask & 0xFF))
+            .collect();
+        // The two low bits are not wired back, then only the two low bits are kept
+        assert_eq!(reads, [(0, 0b1111_1100), (1, 0b0000_0011)]);
+    }
+
+    #[test]
+    fn shift_by_a_variable_is_an_error() {
+        let compiler = compile("let i = 1;\nlet j = 2;\ni = j << i;\n");
+        assert_eq!(
+            compiler.errors,
+            ["Shifts are only supported by a constant amount, but got `i`"]
+        );
+    }
+
+    #[test]
+    fn typed_literal_sizes_the_variable() {
+        let compiler = compile("let i = 5u4;\ni = 15;\ni = 16;\ni = 3u8;\n");
+        assert_eq!(
+            compiler.errors,
+            [
+                "The integer literal `16` does not fit in `i` (4 bits)",
+                "The integer literal `3u8` is wider than `i` (4 bits)",
+            ]
+        );
+    }
+
+    #[test]
+    fn literals_wider_than_the_structures_are_errors() {
+        let compiler = compile("let i = 300u16;\nlet j = 1;\nj + 256u9;\n");
+        assert_eq!(compiler.errors.len(), 2, "{:?}", compiler.errors);
+    }
+
+    #[test]
+    fn block_variables_hide_the_outer_ones_until_the_end_of_the_block() {
+        let compiler = compile("let x = 1;\n{\n    let x = 2;\n    x + 1;\n}\nx + 3;\n");
+        let registers: Vec<&Node> = compiler
+            .nodes
+            .iter()
+            .filter(|node| matches!(node.node, NodeType::Register { .. }))
+            .collect();
+        let adders: Vec<&Node> = compiler
+            .nodes
+            .iter()
+            .filter(|node| matches!(node.node, NodeType::Operator(Operator::Add)))
+            .collect();
+        let reads = |adder: &Node, register: &Node| {
+            compiler
+                .edges
+                .iter()
+                .any(|edge| edge.from_node == register.id && edge.to_node == adder.id)
+        };
+
+        assert!(reads(adders[0], registers[1]) && !reads(adders[0], registers[0]));
+        assert!(reads(adders[1], registers[0]) && !reads(adders[1], registers[1]));
+    }
+
+    #[test]
+    fn writes_run_with_the_control_signal_of_their_step() {
+        let compiler = compile("let i = 0;\ni = i + 1;\ni + 2;\n");
+        let set = compiler
+            .nodes
+            .iter()
+            .find(|node| matches!(node.node, NodeType::Set))
+            .unwrap();
+        assert_eq!(set.step, 1);
+
+        // The start pulse, delayed once to the second statement, goes to each bit of the control input
+        let controls: Vec<&Edge> = compiler
+            .edges
+            .iter()
+            .filter(|edge| edge.to_port == set.inputs[0].id)
+            .collect();
+        assert_eq!(controls.len(), 8);
+        let wait = compiler
+            .nodes
+            .iter()
+            .find(|node| node.id == controls[0].from_node)
+            .unwrap();
+        assert!(matches!(wait.node, NodeType::Wait));
+        assert!(compiler.edges.iter().any(|edge| edge.to_node == wait.id
+            && compiler
+                .nodes
+                .iter()
+                .any(|node| node.id == edge.from_node && matches!(node.node, NodeType::Start))));
+    }
+
+    #[test]
+    fn function_calls_are_built_in_place_with_their_doc() {
+        let compiler = compile(
+            "/// Adds one\nfn inc(a) {\n    a + 1\n}\nlet i = 0;\nlet j = 2;\ni = inc(j);\ninc(i);\n",
+        );
+        assert!(compiler.errors.is_empty(), "{:?}", compiler.errors);
+
+        let adders: Vec<&Node> = compiler
+            .nodes
+            .iter()
+            .filter(|node| matches!(node.node, NodeType::Operator(Operator::Add)))
+            .collect();
+        assert_eq!(adders.len(), 2);
+        assert!(adders
+            .iter()
+            .all(|adder| adder.doc.as_deref() == Some("Adds one")));
+
+        let j = compiler.get_register("j").unwrap();
+        assert!(compiler
+            .edges
+            .iter()
+            .any(|edge| edge.from_node == j.id && edge.to_node == adders[0].id));
+    }
+
+    #[test]
+    fn wrong_calls_are_errors() {
+        let compiler = compile(
+            "fn f(a) {\n    f(a)\n}\nfn g(a, b) {\n    a & b\n}\nlet i = 0;\nf(i);\ng(i);\nh(i);\n",
+        );
+        assert_eq!(
+            compiler.errors,
+            [
+                "The function `f` calls itself, but the calls are built in place so they cannot be recursive",
+                "The function `g` takes 2 argument(s), but got 1",
+                "Unknown function `h`",
+            ]
+        );
+    }
+
+    #[test]
+    fn computed_declarations_are_written_in_their_step() {
+        let compiler = compile(
+            "fn inc(a) {\n    a + 1\n}\nlet i = 0;\nlet j = i + 1;\nlet k = inc(j);\nk + 1;\n",
+        );
+        assert!(compiler.errors.is_empty(), "{:?}", compiler.errors);
+
+        for (name, step) in [("j", 2), ("k", 3)] {
+            let register = compiler.get_register(name).unwrap();
+            let set = compiler
+                .nodes
+                .iter()
+                .find(|node| {
+                    matches!(node.node, NodeType::Set)
+                        && compiler.edges.iter().any(|edge| {
+                            edge.from_node == node.id && edge.to_port == register.inputs[0].id
+                        })
+                })
+                .unwrap();
+            assert_eq!(set.step, step);
+        }
+    }
+
+    #[test]
+    fn declarations_read_the_variables_they_hide() {
+        let compiler = compile("let i = 1;\n{\n    let i = i + 1;\n    i + 2;\n}\n");
+        assert!(compiler.errors.is_empty(), "{:?}", compiler.errors);
+
+        let registers: Vec<&Node> = compiler
+            .nodes
+            .iter()
+            .filter(|node| matches!(node.node, NodeType::Register { .. }))
+            .collect();
+        let adders: Vec<&Node> = compiler
+            .nodes
+            .iter()
+            .filter(|node| matches!(node.node, NodeType::Operator(Operator::Add)))
+            .collect();
+        let reads = |adder: &Node, register: &Node| {
+            compiler
+                .edges
+                .iter()
+                .any(|edge| edge.from_node == register.id && edge.to_node == adder.id)
+        };
+        assert!(reads(adders[0], registers[0]));
+        assert!(reads(adders[1], registers[1]));
+    }
+
+    #[test]
+    fn unknown_variables_are_errors() {
+        let compiler = compile("x + 1;\ny = 2;\nz;\n");
+        assert_eq!(
+            compiler.errors,
+            [
+                "Unknown variable `x`",
+                "Unknown variable `y`",
+                "Unknown variable `z`",
+            ]
+        );
+    }
+
+    #[test]
+    fn unused_shift_is_an_error() {
+        let compiler = compile("let i = 1;\ni << 2;\n");
+        assert_eq!(
+            compiler.errors,
+            ["The result of the shift `i << 2` must be used by an operation or an assignment"]
+        );
+    }
+}
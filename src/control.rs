@@ -0,0 +1,102 @@
+use crate::nodes::{Edge, Node, NodeType};
+
+/// The output of the node driving the control signal of the step. The start structure gives the pulse of the first
+/// step, and a wait structure delays it to each step after it, so the steps run one after the other. The nodes of
+/// the chain are added the first time a step needs them
+fn step_signal(nodes: &mut Vec<Node>, edges: &mut Vec<Edge>, step: usize) -> (String, String) {
+    let mut signal = match nodes
+        .iter()
+        .find(|node| matches!(node.node, NodeType::Start))
+    {
+        Some(start) => (start.id.clone(), start.get_output_id(1, 0)),
+        None => {
+            let start = Node::from(NodeType::Start);
+            let signal = (start.id.clone(), start.get_output_id(1, 0));
+            nodes.push(start);
+            signal
+        }
+    };
+
+    for step in 1..=step {
+        let wait = nodes
+            .iter()
+            .find(|node| matches!(node.node, NodeType::Wait) && node.step == step);
+        signal = match wait {
+            Some(wait) => (wait.id.clone(), wait.get_output_id(1, 0)),
+            None => {
+                let mut wait = Node::from(NodeType::Wait);
+                wait.step = step;
+                edges.push(Edge {
+                    from_node: signal.0,
+                    from_port: signal.1,
+                    to_node: wait.id.clone(),
+                    to_port: wait.get_input_id(1, 0),
+                    shift: 0,
+                    mask: u32::MAX,
+                });
+                let next = (wait.id.clone(), wait.get_output_id(1, 0));
+                nodes.push(wait);
+                next
+            }
+        };
+    }
+
+    signal
+}
+
+/// Wire the control signal of the step to each bit of an input of the node, for the structures gating each bit
+/// of a value with it
+pub fn connect_control(
+    nodes: &mut Vec<Node>,
+    edges: &mut Vec<Edge>,
+    step: usize,
+    to: &Node,
+    port: usize,
+) {
+    let (from_node, from_port) = step_signal(nodes, edges, step);
+    let input = &to.inputs[port];
+    for bit in 0..input.size {
+        edges.push(Edge {
+            from_node: from_node.clone(),
+            from_port: from_port.clone(),
+            to_node: to.id.clone(),
+            to_port: input.id.clone(),
+            shift: bit as i32,
+            mask: u32::MAX,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_are_chained_from_the_start() {
+        let (mut nodes, mut edges) = (Vec::new(), Vec::new());
+        let set = Node::from(NodeType::Set);
+
+        connect_control(&mut nodes, &mut edges, 2, &set, 0);
+        assert_eq!(nodes.len(), 3);
+        assert!(matches!(nodes[0].node, NodeType::Start));
+        assert!(nodes[1..]
+            .iter()
+            .all(|node| matches!(node.node, NodeType::Wait)));
+        // The start to the first wait, the first wait to the second, and the second one to the 8 bits of the input
+        assert_eq!(edges.len(), 2 + 8);
+        assert!(edges[2..]
+            .iter()
+            .all(|edge| edge.from_node == nodes[2].id && edge.to_port == set.inputs[0].id));
+        assert_eq!(
+            edges[2..].iter().map(|edge| edge.shift).collect::<Vec<_>>(),
+            (0..8).collect::<Vec<_>>()
+        );
+
+        // The earlier steps use the same chain
+        connect_control(&mut nodes, &mut edges, 1, &set, 0);
+        connect_control(&mut nodes, &mut edges, 0, &set, 0);
+        assert_eq!(nodes.len(), 3);
+        assert_eq!(edges[10].from_node, nodes[1].id);
+        assert_eq!(edges[18].from_node, nodes[0].id);
+    }
+}
@@ -0,0 +1,167 @@
+use crate::{
+    allocation::redirect,
+    nodes::{Edge, Node, NodeType},
+    parser::ast::Operator,
+};
+use std::collections::HashMap;
+
+/// The value a pure node computes
+#[derive(Debug, PartialEq)]
+enum Computation {
+    Operator(Operator),
+    Int(u32),
+}
+
+/// An input of a pure node: the output it is wired from, and for a register the number of writes it went through,
+/// since the same read output holds another value after each of them
+#[derive(Debug, PartialEq, PartialOrd)]
+struct Source {
+    node_id: String,
+    port_id: String,
+    shift: i32,
+    mask: u32,
+    writes: usize,
+}
+
+/// What makes two nodes compute the same value: the same computation on the same sources
+#[derive(Debug, PartialEq)]
+struct Expression {
+    computation: Computation,
+    sources: Vec<Source>,
+}
+
+/// The nodes computing their output from their inputs alone, which can be read by several consumers
+fn computation(node: &NodeType) -> Option<Computation> {
+    match node {
+        NodeType::Operator(operator) => Some(Computation::Operator(operator.clone())),
+        NodeType::Int(value) => Some(Computation::Int(*value)),
+        _ => None,
+    }
+}
+
+/// The operators giving the same result with their operands swapped
+fn is_commutative(computation: &Computation) -> bool {
+    matches!(
+        computation,
+        Computation::Operator(Operator::Add | Operator::BitAnd | Operator::BitOr)
+    )
+}
+
+/// Remove the nodes computing the same pure expression as an earlier one, and wire their consumers to the earlier
+/// node instead. An operand read from a register is the same only when no write to the register landed between the
+/// two computations. The nodes are compiled after their operands, so merging them in order makes the duplicates of
+/// larger expressions match once their operands are merged. Returns the number of nodes removed
+pub fn eliminate_common_subexpressions(nodes: &mut Vec<Node>, edges: &mut Vec<Edge>) -> usize {
+    let index: HashMap<String, usize> = nodes
+        .iter()
+        .enumerate()
+        .map(|(i, node)| (node.id.clone(), i))
+        .collect();
+
+    // The steps each register is written at, a write landing at the end of its step
+    let mut writes: HashMap<String, Vec<usize>> = HashMap::new();
+    for edge in edges.iter() {
+        let (from, to) = (&nodes[index[&edge.from_node]], &nodes[index[&edge.to_node]]);
+        if matches!(from.node, NodeType::Set)
+            && matches!(to.node, NodeType::Register { .. })
+            && edge.to_port == to.get_input_id(8, 0)
+        {
+            writes.entry(to.id.clone()).or_default().push(from.step);
+        }
+    }
+
+    let mut computed: Vec<(Expression, usize)> = Vec::new();
+    let mut removed = Vec::new();
+    for i in 0..nodes.len() {
+        let Some(computation) = computation(&nodes[i].node) else {
+            continue;
+        };
+
+        let node = &nodes[i];
+        let mut inputs: Vec<(usize, Source)> = edges
+            .iter()
+            .filter(|edge| edge.to_node == node.id)
+            .map(|edge| {
+                let port = node
+                    .inputs
+                    .iter()
+                    .position(|p| p.id == edge.to_port)
+                    .unwrap();
+                let writes = writes.get(&edge.from_node).map_or(0, |steps| {
+                    steps.iter().filter(|&&step| step < node.step).count()
+                });
+                let source = Source {
+                    node_id: edge.from_node.clone(),
+                    port_id: edge.from_port.clone(),
+                    shift: edge.shift,
+                    mask: edge.mask,
+                    writes,
+                };
+                (port, source)
+            })
+            .collect();
+        inputs.sort_by_key(|(port, _)| *port);
+
+        let mut sources: Vec<Source> = inputs.into_iter().map(|(_, source)| source).collect();
+        if is_commutative(&computation) {
+            sources.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        }
+        let expression = Expression {
+            computation,
+            sources,
+        };
+
+        match computed.iter().find(|(other, _)| *other == expression) {
+            Some(&(_, first)) => {
+                let duplicate = nodes[i].clone();
+                edges.retain(|edge| edge.to_node != duplicate.id);
+                redirect(edges, &duplicate, &nodes[first]);
+                removed.push(duplicate.id);
+            }
+            None => computed.push((expression, i)),
+        }
+    }
+
+    nodes.retain(|node| !removed.contains(&node.id));
+    removed.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{compiler::Compiler, parser::parser::parse};
+
+    /// The number of additions left once the common subexpressions of the program are eliminated
+    fn additions(program: &str) -> usize {
+        let (ast, errors) = parse(program);
+        assert!(errors.is_empty(), "{errors:?}");
+        let mut compiler = Compiler::new();
+        compiler.compile(ast);
+        assert!(compiler.errors.is_empty(), "{:?}", compiler.errors);
+
+        eliminate_common_subexpressions(&mut compiler.nodes, &mut compiler.edges);
+        compiler
+            .nodes
+            .iter()
+            .filter(|node| matches!(node.node, NodeType::Operator(Operator::Add)))
+            .count()
+    }
+
+    #[test]
+    fn reads_of_a_register_are_merged_until_it_is_written() {
+        assert_eq!(
+            additions("let i = 1;\nlet j = 2;\nj = i + 1;\nj = 1 + i;\n"),
+            1
+        );
+        // The second addition reads the value written between them
+        assert_eq!(
+            additions("let i = 1;\nlet j = 2;\nj = i + 1;\ni = 3;\nj = i + 1;\n"),
+            2
+        );
+        // Writing another register does not change what they read
+        assert_eq!(
+            additions("let i = 1;\nlet j = 2;\nj = i + 1;\nj = 3;\nj = i + 1;\n"),
+            1
+        );
+    }
+}
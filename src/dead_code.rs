@@ -0,0 +1,421 @@
+use crate::parser::{
+    ast::{BlockStatement, Expr, Operator, Statement},
+    formatter::print_expr,
+};
+use std::collections::{HashMap, HashSet};
+
+/// Remove the code that can never change what the circuit does, before building a structure for it: the branches
+/// of the conditions whose value is known, the statements after a `break` or a `return` always taken, and the
+/// variables never read, with all their assignments. Returns the program left and a warning for each removal
+pub fn eliminate_dead_code(statements: BlockStatement) -> (BlockStatement, Vec<String>) {
+    let mut warnings = Vec::new();
+    let mut statements = remove_unreachable(statements, &mut warnings);
+
+    // Removing a variable removes the reads in its assignments, which can leave other variables unread
+    loop {
+        let mut variables = Variables::default();
+        variables.walk_block(&mut statements, false);
+        let unread = variables.unread();
+        if unread.is_empty() {
+            break;
+        }
+
+        let mut removal = Variables {
+            removed: Some(unread.iter().map(|&(id, _)| (id, 0)).collect()),
+            ..Variables::default()
+        };
+        removal.walk_block(&mut statements, false);
+        let assignments = removal.removed.unwrap_or_default();
+        for (id, name) in unread {
+            warnings.push(match assignments[&id] {
+                0 => format!("The variable `{name}` is never read, it was removed"),
+                n => format!("The variable `{name}` is never read, it was removed with its {n} assignment(s)"),
+            });
+        }
+    }
+
+    (statements, warnings)
+}
+
+/// Replace the conditions whose value is known by the branch taken, and remove the statements following one that
+/// always leaves the block
+fn remove_unreachable(statements: BlockStatement, warnings: &mut Vec<String>) -> BlockStatement {
+    let mut block = Vec::new();
+    let mut statements = statements.into_iter();
+
+    while let Some(statement) = statements.next() {
+        let statement = match statement {
+            Statement::Expression(Expr::If {
+                condition,
+                consequence,
+                alternative,
+            }) => match constant(&condition) {
+                Some(value) => {
+                    let (taken, kind) = if value != 0 {
+                        (Some(consequence), "true, the else branch")
+                    } else {
+                        (alternative, "false, the if branch")
+                    };
+                    warnings.push(format!(
+                        "The condition `{}` is always {kind} was removed",
+                        print_expr(&condition)
+                    ));
+
+                    match taken {
+                        Some(taken) => Statement::Block(remove_unreachable(taken, warnings)),
+                        None => continue,
+                    }
+                }
+                None => Statement::Expression(Expr::If {
+                    condition,
+                    consequence: remove_unreachable(consequence, warnings),
+                    alternative: alternative
+                        .map(|alternative| remove_unreachable(alternative, warnings)),
+                }),
+            },
+            Statement::Expression(Expr::Loop { body }) => Statement::Expression(Expr::Loop {
+                body: remove_unreachable(body, warnings),
+            }),
+            // The body of a function is built at each call, with its own dead code
+            Statement::Expression(Expr::Function {
+                name,
+                parameters,
+                body,
+                doc,
+            }) => Statement::Expression(Expr::Function {
+                name,
+                parameters,
+                body: remove_unreachable(body, warnings),
+                doc,
+            }),
+            Statement::Block(inner) => Statement::Block(remove_unreachable(inner, warnings)),
+            statement => statement,
+        };
+
+        let leaves = always_leaves(&statement);
+        block.push(statement);
+        if leaves {
+            let removed = statements.count();
+            if removed > 0 {
+                warnings.push(format!(
+                    "{removed} statement(s) after a break or a return can never run and were removed"
+                ));
+            }
+            break;
+        }
+    }
+
+    block
+}
+
+/// Whether the statement always leaves the block, so that the following ones never run
+fn always_leaves(statement: &Statement) -> bool {
+    match statement {
+        Statement::Expression(Expr::Break) | Statement::Return(_) => true,
+        // A branch taken was put in a block, the loops and the other conditions are left to their own blocks
+        Statement::Block(block) => block.iter().any(always_leaves),
+        _ => false,
+    }
+}
+
+/// The value of an expression made of literals only. The booleans are 1 and 0, and the integers wrap on 8 bits
+/// like the structures computing them
+fn constant(expr: &Expr) -> Option<u32> {
+    let value = match expr {
+        Expr::Int { value, .. } => *value,
+        Expr::Bool { value } => *value as u32,
+        Expr::Prefix { operator, right } => {
+            let right = constant(right)?;
+            match operator {
+                Operator::Not => (right == 0) as u32,
+                Operator::BitNot => !right,
+                Operator::Negate | Operator::Subtract => right.wrapping_neg(),
+                _ => return None,
+            }
+        }
+        Expr::Infix {
+            left,
+            operator,
+            right,
+        } => {
+            let (left, right) = (constant(left)?, constant(right)?);
+            match operator {
+                Operator::Add => left.wrapping_add(right),
+                Operator::Subtract => left.wrapping_sub(right),
+                Operator::Multiply => left.wrapping_mul(right),
+                Operator::BitAnd => left & right,
+                Operator::BitOr => left | right,
+                Operator::BitXor => left ^ right,
+                Operator::ShiftLeft => left.checked_shl(right).unwrap_or(0),
+                Operator::ShiftRight => left.checked_shr(right).unwrap_or(0),
+                Operator::Gt => (left > right) as u32,
+                Operator::Gte => (left >= right) as u32,
+                Operator::Lt => (left < right) as u32,
+                Operator::Lte => (left <= right) as u32,
+                Operator::Eq => (left == right) as u32,
+                Operator::Ne => (left != right) as u32,
+                Operator::And => (left != 0 && right != 0) as u32,
+                Operator::Or => (left != 0 || right != 0) as u32,
+                _ => return None,
+            }
+        }
+        _ => return None,
+    };
+
+    Some(value & 0xFF)
+}
+
+/// The declarations of the variables outside the functions, numbered in the order of the program, resolved by the
+/// scopes of the blocks. The same walk over the program finds the declarations read, then removes the other ones
+#[derive(Default)]
+struct Variables {
+    /// The name of each declaration, and whether it is read
+    declarations: Vec<(String, bool)>,
+    /// The names used in the functions without being declared there. A function is built where it is called, so
+    /// they can stand for any declaration with the name
+    free: HashSet<String>,
+    /// The declarations visible in each block being walked, `None` for the parameters and the variables of the
+    /// functions
+    scopes: Vec<Vec<(String, Option<usize>)>>,
+    /// When removing, the declarations to remove with the number of assignments removed with each one
+    removed: Option<HashMap<usize, usize>>,
+}
+
+impl Variables {
+    /// The declarations never read, with their names
+    fn unread(&self) -> Vec<(usize, String)> {
+        self.declarations
+            .iter()
+            .enumerate()
+            .filter(|(_, (name, read))| !read && !self.free.contains(name))
+            .map(|(id, (name, _))| (id, name.clone()))
+            .collect()
+    }
+
+    /// The declaration the name stands for where it is used: `None` when it is not declared, `Some(None)` when it
+    /// is declared in a function
+    fn resolve(&self, name: &str) -> Option<Option<usize>> {
+        self.scopes
+            .iter()
+            .rev()
+            .flat_map(|scope| scope.iter().rev())
+            .find(|(declared, _)| declared == name)
+            .map(|&(_, id)| id)
+    }
+
+    fn declare(&mut self, name: &str, in_function: bool) -> Option<usize> {
+        let id = (!in_function).then_some(self.declarations.len());
+        if id.is_some() {
+            self.declarations.push((name.to_string(), false));
+        }
+        self.scopes
+            .last_mut()
+            .expect("a block is being walked")
+            .push((name.to_string(), id));
+        id
+    }
+
+    /// Whether the declaration is being removed, counting the assignment when there is one
+    fn is_removed(&mut self, id: Option<usize>, assignment: bool) -> bool {
+        let Some(count) = id.and_then(|id| self.removed.as_mut()?.get_mut(&id)) else {
+            return false;
+        };
+        *count += assignment as usize;
+        true
+    }
+
+    fn walk_block(&mut self, statements: &mut BlockStatement, in_function: bool) {
+        self.scopes.push(Vec::new());
+        statements.retain_mut(|statement| self.walk_statement(statement, in_function));
+        self.scopes.pop();
+    }
+
+    /// Walk the statement, and tell whether it is kept. Writing to a variable is not a read, unless the assignment
+    /// is nested in another expression which is then kept, and the variable with it. A declared or assigned value
+    /// calling a function is kept as a statement of its own when the variable is removed, since the call can do
+    /// more than computing the value
+    fn walk_statement(&mut self, statement: &mut Statement, in_function: bool) -> bool {
+        match statement {
+            Statement::Let(name, expr, _) => {
+                self.walk_expr(expr, in_function);
+                let id = self.declare(name, in_function);
+                if !self.is_removed(id, false) {
+                    true
+                } else if has_call(expr) {
+                    *statement = Statement::Expression(expr.clone());
+                    true
+                } else {
+                    false
+                }
+            }
+            Statement::Expression(Expr::Assignment { left, right })
+                if matches!(**left, Expr::Identifier(_)) =>
+            {
+                self.walk_expr(right, in_function);
+                let Expr::Identifier(name) = &**left else {
+                    unreachable!()
+                };
+                let id = match self.resolve(name) {
+                    Some(id) => id,
+                    None => {
+                        self.free.insert(name.clone());
+                        None
+                    }
+                };
+                if !self.is_removed(id, true) {
+                    true
+                } else if has_call(right) {
+                    *statement = Statement::Expression(*right.clone());
+                    true
+                } else {
+                    false
+                }
+            }
+            Statement::Expression(expr) | Statement::Return(expr) => {
+                self.walk_expr(expr, in_function);
+                true
+            }
+            Statement::Block(block) => {
+                self.walk_block(block, in_function);
+                true
+            }
+            Statement::Trivia(_) => true,
+        }
+    }
+
+    fn walk_expr(&mut self, expr: &mut Expr, in_function: bool) {
+        match expr {
+            Expr::Identifier(name) => match self.resolve(name) {
+                Some(Some(id)) => self.declarations[id].1 = true,
+                Some(None) => (),
+                None => {
+                    self.free.insert(name.clone());
+                }
+            },
+            Expr::Prefix { right, .. } => self.walk_expr(right, in_function),
+            Expr::Infix { left, right, .. }
+            | Expr::Assignment { left, right }
+            | Expr::Member { left, right, .. }
+            | Expr::Index { left, index: right } => {
+                self.walk_expr(left, in_function);
+                self.walk_expr(right, in_function);
+            }
+            Expr::Loop { body } => self.walk_block(body, in_function),
+            Expr::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.walk_expr(condition, in_function);
+                self.walk_block(consequence, in_function);
+                if let Some(alternative) = alternative {
+                    self.walk_block(alternative, in_function);
+                }
+            }
+            // The body only sees its parameters and its own variables, the other names are resolved where it is
+            // called
+            Expr::Function {
+                parameters, body, ..
+            } => {
+                let outer = std::mem::take(&mut self.scopes);
+                self.scopes
+                    .push(parameters.iter().map(|name| (name.clone(), None)).collect());
+                self.walk_block(body, true);
+                self.scopes = outer;
+            }
+            Expr::Call { left, arguments } => {
+                self.walk_expr(left, in_function);
+                for argument in arguments {
+                    self.walk_expr(argument, in_function);
+                }
+            }
+            Expr::Array { values } => {
+                for value in values {
+                    self.walk_expr(value, in_function);
+                }
+            }
+            Expr::Int { .. } | Expr::Bool { .. } | Expr::String { .. } | Expr::Break => (),
+        }
+    }
+}
+
+/// Whether computing the expression calls a function
+fn has_call(expr: &Expr) -> bool {
+    match expr {
+        Expr::Call { .. } => true,
+        Expr::Prefix { right, .. } => has_call(right),
+        Expr::Infix { left, right, .. } | Expr::Assignment { left, right } => {
+            has_call(left) || has_call(right)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{formatter::print, parser::parse};
+
+    fn eliminate(program: &str) -> (String, Vec<String>) {
+        let (ast, errors) = parse(program);
+        assert!(errors.is_empty(), "{errors:?}");
+        let (statements, warnings) = eliminate_dead_code(ast);
+        (print(&statements), warnings)
+    }
+
+    #[test]
+    fn shadowed_declarations_are_resolved() {
+        let (program, warnings) = eliminate("let x = 1;\nlet y = x;\nlet x = 2;\ny + 1;\n");
+        assert_eq!(program, "let x = 1;\nlet y = x;\ny + 1;\n");
+        assert_eq!(warnings, ["The variable `x` is never read, it was removed"]);
+
+        let (program, _) = eliminate("let x = 1;\nx + 1;\n{\n    let x = 2;\n    x = 3;\n}\n");
+        assert_eq!(program, "let x = 1;\nx + 1;\n{}\n");
+    }
+
+    #[test]
+    fn variables_are_visible_in_their_block_only() {
+        let (program, warnings) = eliminate("let x = 1;\n{\n    let x = 2;\n    x + 1;\n}\n");
+        assert_eq!(program, "{\n    let x = 2;\n    x + 1;\n}\n");
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn names_used_by_functions_are_kept() {
+        let program = "let x = 1;\nfn f() {\n    x += 1;\n}\nf();\n";
+        assert_eq!(eliminate(program), (program.to_string(), vec![]));
+    }
+
+    #[test]
+    fn calls_of_removed_variables_are_kept() {
+        let program = "fn f() {\n    1;\n}\nlet x = f();\nlet y = 0;\ny = f() + 1;\n";
+        let (program, warnings) = eliminate(program);
+        assert_eq!(program, "fn f() {\n    1;\n}\nf();\nf() + 1;\n");
+        assert_eq!(warnings.len(), 2);
+    }
+
+    #[test]
+    fn constant_branches_of_functions_are_removed() {
+        let (program, warnings) = eliminate(
+            "let x = 0;\nfn f() {\n    if 4 > 6 {\n        x = 1;\n    }\n    x + 1\n}\nf();\n",
+        );
+        assert_eq!(program, "let x = 0;\nfn f() {\n    x + 1;\n}\nf();\n");
+        assert_eq!(
+            warnings,
+            ["The condition `4 > 6` is always false, the if branch was removed"]
+        );
+    }
+
+    #[test]
+    fn assigned_only_variables_are_removed() {
+        let (program, warnings) = eliminate("let i = 0;\ni = 5;\nlet j = i;\n");
+        assert_eq!(program, "");
+        assert_eq!(
+            warnings,
+            [
+                "The variable `j` is never read, it was removed",
+                "The variable `i` is never read, it was removed with its 1 assignment(s)"
+            ]
+        );
+    }
+}
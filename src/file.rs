@@ -1,6 +1,6 @@
 use std::{
     fs::File,
-    io::{self, Read},
+    io::{self, Read, Write},
 };
 
 pub fn read_file_code() -> Result<String, io::Error> {
@@ -11,3 +11,8 @@ pub fn read_file_code() -> Result<String, io::Error> {
 
     Ok(content)
 }
+
+pub fn write_file_code(content: &str) -> Result<(), io::Error> {
+    let mut file = File::create("main.redstone")?;
+    file.write_all(content.as_bytes())
+}
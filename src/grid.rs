@@ -0,0 +1,335 @@
+use crate::{pathfinding::Wire, vectors::Vector3};
+use std::collections::HashSet;
+
+/// Cost of each wire already near a cell on the first iteration, and how much it grows each iteration
+const PRESENT_FACTOR_START: f32 = 0.5;
+const PRESENT_FACTOR_GROWTH: f32 = 1.6;
+/// Extra cost of a cell for each iteration it ended congested
+const HISTORY_FACTOR: f32 = 0.5;
+/// Cost of a cell next to a port, decreasing with the distance to it
+const PORT_COST: f32 = 100.0;
+/// Distance up to which a port makes the cells around it more expensive
+const PORT_RADIUS: i32 = 8;
+
+/// Lowest cost of a wire to a port from the cells at each distance to it
+pub struct CostsToPort {
+    /// From each distance up to the edge of the ports area
+    near: Vec<u32>,
+    min_step_cost: u32,
+}
+
+impl CostsToPort {
+    /// The lowest cost from a cell at the distance to the port
+    pub fn at(&self, distance: u32) -> u32 {
+        let edge = self.near.len() as u32 - 1;
+        let near = distance.min(edge);
+        self.near[near as usize] + (distance - near) * self.min_step_cost
+    }
+}
+
+/// Dense 3D grid over the routing volume, holding everything the search needs to know about a cell so that each
+/// expansion is a few array lookups:
+/// - the number of obstacles within one block, so a wire keeps its clearance
+/// - the cost of going near the ports
+/// - the negotiated congestion: the wires the cell currently couples to, and how often it ended congested
+pub struct RoutingGrid {
+    min: Vector3,
+    max: Vector3,
+    /// Number of cells along y and z, to get the index of a position
+    size_y: usize,
+    size_z: usize,
+    obstacles_nearby: Vec<u16>,
+    port_cost: Vec<f32>,
+    /// Lowest cost of going through a cell away from the ports
+    min_step_cost: u32,
+    /// Number of wires each cell would couple to
+    usage: Vec<u16>,
+    /// Number of iterations each cell ended congested
+    history: Vec<u16>,
+    /// Cost of each wire already near a cell. It grows every iteration, until the wires stop sharing cells
+    present_factor: f32,
+}
+
+impl RoutingGrid {
+    /// Create the grid of the volume between `min` and `max` (included), with the obstacles and the ports in it
+    pub fn new(
+        min: Vector3,
+        max: Vector3,
+        obstacles: &HashSet<Vector3>,
+        entries: &[Vector3],
+    ) -> Self {
+        let size_x = (max.0 - min.0 + 1) as usize;
+        let size_y = (max.1 - min.1 + 1) as usize;
+        let size_z = (max.2 - min.2 + 1) as usize;
+        let cells = size_x * size_y * size_z;
+
+        let mut grid = Self {
+            min,
+            max,
+            size_y,
+            size_z,
+            obstacles_nearby: vec![0; cells],
+            port_cost: vec![0.0; cells],
+            min_step_cost: 1,
+            usage: vec![0; cells],
+            history: vec![0; cells],
+            present_factor: PRESENT_FACTOR_START,
+        };
+
+        for &obstacle in obstacles {
+            grid.add_obstacle(obstacle);
+        }
+
+        // The cells on a port cost as much as the ones next to it
+        for &entry in entries {
+            for x in -PORT_RADIUS..=PORT_RADIUS {
+                for y in -PORT_RADIUS..=PORT_RADIUS {
+                    for z in -PORT_RADIUS..=PORT_RADIUS {
+                        let pos = entry + Vector3(x, y, z);
+                        let distance = pos.distance(&entry);
+                        if distance as i32 <= PORT_RADIUS && grid.contains(pos) {
+                            let i = grid.index(pos);
+                            grid.port_cost[i] += PORT_COST / distance.max(1) as f32;
+                        }
+                    }
+                }
+            }
+        }
+
+        let min_port_cost = grid.port_cost.iter().copied().fold(f32::MAX, f32::min);
+        grid.min_step_cost = (1.0 + min_port_cost) as u32;
+
+        grid
+    }
+
+    /// Whether the position is inside the volume the wires can be built in
+    pub fn contains(&self, pos: Vector3) -> bool {
+        (self.min.0..=self.max.0).contains(&pos.0)
+            && (self.min.1..=self.max.1).contains(&pos.1)
+            && (self.min.2..=self.max.2).contains(&pos.2)
+    }
+
+    /// Number of cells of the grid
+    pub fn cells(&self) -> usize {
+        self.usage.len()
+    }
+
+    /// Index of the cell in the grid, from 0 to `cells`
+    pub fn index(&self, pos: Vector3) -> usize {
+        let Vector3(x, y, z) = pos - self.min;
+        (x as usize * self.size_y + y as usize) * self.size_z + z as usize
+    }
+
+    /// Calls `f` with the index of each cell of the grid within one block of the position
+    fn for_each_around(&self, pos: Vector3, mut f: impl FnMut(usize)) {
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let cell = Vector3(pos.0 + x, pos.1 + y, pos.2 + z);
+                    if self.contains(cell) {
+                        f(self.index(cell));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Register a block the wires must keep away from. Both the wool of a wire block and the redstone on top of it
+    /// keep one block away from the obstacle
+    pub fn add_obstacle(&mut self, pos: Vector3) {
+        let mut cells = Vec::new();
+        self.for_each_around(pos, |i| cells.push(i));
+        self.for_each_around(pos - Vector3(0, 1, 0), |i| cells.push(i));
+        cells.sort_unstable();
+        cells.dedup();
+        for i in cells {
+            self.obstacles_nearby[i] += 1;
+        }
+    }
+
+    /// Whether a wire block on the position would be within one block of an obstacle. Outside of the grid,
+    /// everything is an obstacle
+    pub fn is_obstacle_nearby(&self, pos: Vector3) -> bool {
+        !self.contains(pos) || self.obstacles_nearby[self.index(pos)] > 0
+    }
+
+    /// Lowest cost of a wire to the port from the cells at each distance to it, the costs of the steps being divided
+    /// by `discount`. Near a port the wire takes single steps, so it goes through a cell at each distance to the
+    /// port, and the cheapest free cell at that distance is a lower bound of its cost there, with the costs of the
+    /// ports around. The cells next to the port cost 1
+    pub fn costs_to_port(&self, port: Vector3, discount: u32) -> CostsToPort {
+        let radius = PORT_RADIUS as usize;
+        let mut cheapest = vec![u32::MAX; radius + 1];
+        for x in -PORT_RADIUS..=PORT_RADIUS {
+            for y in -PORT_RADIUS..=PORT_RADIUS {
+                for z in -PORT_RADIUS..=PORT_RADIUS {
+                    let pos = port + Vector3(x, y, z);
+                    let distance = pos.distance(&port) as usize;
+                    if (2..=radius).contains(&distance) && !self.is_obstacle_nearby(pos) {
+                        let cost = (1.0 + self.port_cost(pos)) as u32 / discount;
+                        cheapest[distance] = cheapest[distance].min(cost.max(self.min_step_cost));
+                    }
+                }
+            }
+        }
+
+        // Without any free cell at a distance, the wire cannot reach the port, the cost is left to the search
+        let mut near = vec![0];
+        for (distance, &cost) in cheapest.iter().enumerate() {
+            let step = match cost {
+                _ if distance < 2 => 1,
+                u32::MAX => self.min_step_cost,
+                cost => cost,
+            };
+            near.push(near[distance] + step);
+        }
+
+        CostsToPort {
+            near,
+            min_step_cost: self.min_step_cost,
+        }
+    }
+
+    /// Cost of going near the ports, to keep them free for their own wires
+    pub fn port_cost(&self, pos: Vector3) -> f32 {
+        self.port_cost[self.index(pos)]
+    }
+
+    /// Number of wires the position would couple to
+    pub fn usage(&self, pos: &Vector3) -> u32 {
+        if self.contains(*pos) {
+            self.usage[self.index(*pos)] as u32
+        } else {
+            0
+        }
+    }
+
+    /// The cost of going through the cell, from its base cost
+    pub fn cost(&self, pos: Vector3, base: f32) -> f32 {
+        let i = self.index(pos);
+        let history = self.history[i] as f32;
+        let present = self.usage[i] as f32;
+
+        base * (1.0 + history * HISTORY_FACTOR) * (1.0 + present * self.present_factor)
+    }
+
+    /// Register the cells the wire would couple to as used
+    pub fn add_wire(&mut self, wire: &Wire) {
+        for i in self.footprint(wire) {
+            self.usage[i] += 1;
+        }
+    }
+
+    /// Rip up the wire
+    pub fn remove_wire(&mut self, wire: &Wire) {
+        for i in self.footprint(wire) {
+            self.usage[i] -= 1;
+        }
+    }
+
+    /// Rip up all the wires
+    pub fn clear_wires(&mut self) {
+        self.usage.fill(0);
+    }
+
+    /// The cells a wire keeps the others away from: the ones its blocks would couple to
+    fn footprint(&self, wire: &Wire) -> HashSet<usize> {
+        wire.coupled_blocks()
+            .filter(|&pos| self.contains(pos))
+            .map(|pos| self.index(pos))
+            .collect()
+    }
+
+    /// Make the cell more expensive for the next iterations, since it ended congested
+    pub fn add_history(&mut self, pos: Vector3) {
+        let i = self.index(pos);
+        self.history[i] += 1;
+    }
+
+    /// Make the wires sharing cells more expensive for the next iteration
+    pub fn next_iteration(&mut self) {
+        self.present_factor *= PRESENT_FACTOR_GROWTH;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::redstone::WireBlock;
+
+    fn grid(obstacles: &[Vector3], entries: &[Vector3]) -> RoutingGrid {
+        let obstacles = obstacles.iter().copied().collect();
+        RoutingGrid::new(Vector3(0, 0, 0), Vector3(29, 9, 29), &obstacles, entries)
+    }
+
+    #[test]
+    fn ports_cost_more_up_to_their_radius() {
+        let port = Vector3(10, 0, 10);
+        let grid = grid(&[], &[port, Vector3(12, 0, 10)]);
+
+        assert_eq!(grid.port_cost(port), PORT_COST + PORT_COST / 2.0);
+        assert_eq!(grid.port_cost(Vector3(11, 0, 10)), 2.0 * PORT_COST);
+        assert_eq!(grid.port_cost(Vector3(10, 0, 2)), PORT_COST / 8.0);
+        assert_eq!(grid.port_cost(Vector3(10, 0, 1)), 0.0);
+        assert_eq!(grid.port_cost(Vector3(29, 9, 29)), 0.0);
+    }
+
+    #[test]
+    fn wires_cross_the_ports_area_to_reach_a_port() {
+        let port = Vector3(10, 0, 10);
+        let costs = grid(&[], &[port]).costs_to_port(port, 1);
+        assert_eq!(costs.at(0), 0);
+        assert_eq!(costs.at(2), 2);
+        assert_eq!(costs.at(3), 2 + 51);
+        // Away from the port, the same as the distance
+        let area = costs.at(PORT_RADIUS as u32 + 1);
+        assert_eq!(costs.at(PORT_RADIUS as u32 + 5), area + 4);
+
+        // The cells around the port are all within 4 blocks of the next port
+        let grid = grid(&[], &[port, Vector3(12, 0, 10)]);
+        assert_eq!(grid.costs_to_port(port, 1).at(3), 2 + 76);
+        assert!(grid.costs_to_port(port, 4).at(3) < 2 + 76);
+    }
+
+    #[test]
+    fn obstacles_keep_the_wires_one_block_away() {
+        let grid = grid(&[Vector3(5, 5, 5)], &[]);
+
+        assert!(grid.is_obstacle_nearby(Vector3(6, 6, 6)));
+        assert!(grid.is_obstacle_nearby(Vector3(4, 4, 4)));
+        // The redstone on top of a wire block would touch the obstacle
+        assert!(grid.is_obstacle_nearby(Vector3(5, 3, 5)));
+        assert!(!grid.is_obstacle_nearby(Vector3(5, 2, 5)));
+        assert!(!grid.is_obstacle_nearby(Vector3(7, 5, 5)));
+        assert!(grid.is_obstacle_nearby(Vector3(-1, 0, 0)));
+    }
+
+    #[test]
+    fn wires_use_the_cells_they_couple_to_until_ripped_up() {
+        let mut grid = grid(&[], &[]);
+        let wire = Wire {
+            path: (2..6).map(|x| Vector3(x, 0, 5)).collect(),
+            blocks: vec![WireBlock::Redstone; 4],
+        };
+        let cost = grid.cost(Vector3(3, 0, 5), 1.0);
+
+        grid.add_wire(&wire);
+        grid.add_wire(&wire);
+        assert_eq!(grid.usage(&Vector3(3, 0, 5)), 2);
+        assert_eq!(grid.usage(&Vector3(3, 0, 20)), 0);
+        assert!(grid.cost(Vector3(3, 0, 5), 1.0) > cost);
+
+        grid.remove_wire(&wire);
+        assert_eq!(grid.usage(&Vector3(3, 0, 5)), 1);
+        grid.clear_wires();
+        assert_eq!(grid.usage(&Vector3(3, 0, 5)), 0);
+
+        // A cell that ended congested stays more expensive, more so each iteration it is shared
+        grid.add_history(Vector3(3, 0, 5));
+        assert_eq!(grid.cost(Vector3(3, 0, 5), 1.0), 1.0 + HISTORY_FACTOR);
+        grid.add_wire(&wire);
+        let shared = grid.cost(Vector3(3, 0, 5), 1.0);
+        grid.next_iteration();
+        assert!(grid.cost(Vector3(3, 0, 5), 1.0) > shared);
+    }
+}
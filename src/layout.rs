@@ -0,0 +1,169 @@
+use crate::{placement::PlacementKind, redstone::TOWER_STEP, router::RouterKind, vectors::Vector3};
+
+/// Lowest and highest blocks of a Minecraft world
+const WORLD_MIN_Y: i32 = -64;
+const WORLD_MAX_Y: i32 = 319;
+
+/// Where the structures are placed and how the wires are routed, shared by the builder and the pathfinding
+#[derive(Debug, Clone)]
+pub struct LayoutConfig {
+    /// Position of the first structure, the others are placed from there
+    pub origin: Vector3,
+    /// How the structures are arranged
+    pub placement: PlacementKind,
+    /// Length of a row of structures along x before starting the next one
+    pub grid_width: i32,
+    /// Space left between the structures
+    pub spacing: i32,
+    /// Space around the structures the wires can be routed in
+    pub margin: i32,
+    /// Number of routing layers above the margin, one tower level apart, for the wires to climb over the others
+    pub layers: i32,
+    /// Fixed volume to route the wires in (lowest and highest corners), instead of the one around the structures
+    pub bounds: Option<(Vector3, Vector3)>,
+    /// Search algorithm of the wires
+    pub router: RouterKind,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        Self {
+            origin: Vector3(0, 150, 0),
+            placement: PlacementKind::default(),
+            grid_width: 50,
+            spacing: 10,
+            margin: 10,
+            layers: 2,
+            bounds: None,
+            router: RouterKind::default(),
+        }
+    }
+}
+
+impl LayoutConfig {
+    /// The default layout, changed by the options of the command line: `--router=`, `--placement=`, `--origin=x,y,z`,
+    /// `--margin=`, `--layers=` and `--bounds=x,y,z:x,y,z`
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, String> {
+        let mut layout = Self::default();
+        for arg in args {
+            let Some((option, value)) = arg.split_once('=') else {
+                continue;
+            };
+            match option {
+                "--router" => layout.router = value.parse()?,
+                "--placement" => layout.placement = value.parse()?,
+                "--origin" => layout.origin = value.parse()?,
+                "--margin" => layout.margin = parse_count(option, value)?,
+                "--layers" => layout.layers = parse_count(option, value)?,
+                "--bounds" => {
+                    let (min, max) = value.split_once(':').ok_or_else(|| {
+                        format!("Invalid bounds {value:?}, expected \"x,y,z:x,y,z\"")
+                    })?;
+                    let (min, max): (Vector3, Vector3) = (min.parse()?, max.parse()?);
+                    if min.0 > max.0 || min.1 > max.1 || min.2 > max.2 {
+                        return Err(format!(
+                            "Invalid bounds {value:?}, the first corner must be the lowest one"
+                        ));
+                    }
+                    layout.bounds = Some((min, max));
+                }
+                _ => return Err(format!("Unknown option {option:?}")),
+            }
+        }
+        Ok(layout)
+    }
+
+    /// The volume the wires can be routed in: the fixed bounds, or the box around the placed blocks plus the margin
+    /// and the routing layers. It never goes past the height limits of the world
+    pub fn routing_bounds<'a>(
+        &self,
+        blocks: impl IntoIterator<Item = &'a Vector3>,
+    ) -> (Vector3, Vector3) {
+        let (min, max) = self.bounds.unwrap_or_else(|| {
+            let mut blocks = blocks.into_iter();
+            let first = *blocks.next().unwrap_or(&self.origin);
+            let (min, max) = blocks.fold((first, first), |(min, max), pos| {
+                (
+                    Vector3(min.0.min(pos.0), min.1.min(pos.1), min.2.min(pos.2)),
+                    Vector3(max.0.max(pos.0), max.1.max(pos.1), max.2.max(pos.2)),
+                )
+            });
+
+            let margin = Vector3(self.margin, self.margin, self.margin);
+            let layers = Vector3(0, self.layers * TOWER_STEP, 0);
+            (min - margin, max + margin + layers)
+        });
+
+        (
+            Vector3(min.0, min.1.max(WORLD_MIN_Y), min.2),
+            Vector3(max.0, max.1.min(WORLD_MAX_Y), max.2),
+        )
+    }
+}
+
+/// A number of blocks given to an option, which cannot be negative
+fn parse_count(option: &str, value: &str) -> Result<i32, String> {
+    value
+        .parse()
+        .ok()
+        .filter(|&count: &i32| count >= 0)
+        .ok_or_else(|| format!("Invalid value {value:?} for {option}, expected a number of blocks"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn layout(args: &[&str]) -> Result<LayoutConfig, String> {
+        LayoutConfig::from_args(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn options_change_the_layout() {
+        let layout = layout(&[
+            "--share-structures",
+            "--router=lee",
+            "--origin=10,-20,30",
+            "--margin=4",
+            "--layers=0",
+            "--bounds=0,0,0:100,200,100",
+        ])
+        .unwrap();
+        assert_eq!(layout.router, RouterKind::Lee);
+        assert_eq!(layout.origin, Vector3(10, -20, 30));
+        assert_eq!(layout.margin, 4);
+        assert_eq!(layout.layers, 0);
+        assert_eq!(
+            layout.bounds,
+            Some((Vector3(0, 0, 0), Vector3(100, 200, 100)))
+        );
+    }
+
+    #[test]
+    fn invalid_options_are_errors() {
+        assert!(layout(&["--origin=1,2"]).is_err());
+        assert!(layout(&["--margin=-1"]).is_err());
+        assert!(layout(&["--layers=two"]).is_err());
+        assert!(layout(&["--bounds=0,0,0"]).is_err());
+        assert!(layout(&["--bounds=10,0,0:0,10,10"]).is_err());
+        assert!(layout(&["--router=dijkstra"]).is_err());
+        assert_eq!(
+            layout(&["--rooter=lee"]).err().as_deref(),
+            Some("Unknown option \"--rooter\"")
+        );
+    }
+
+    #[test]
+    fn routing_bounds_stay_in_the_world() {
+        let layout = LayoutConfig {
+            margin: 2,
+            layers: 1,
+            ..LayoutConfig::default()
+        };
+        let blocks = [Vector3(0, -63, 0), Vector3(5, 10, 5)];
+        assert_eq!(
+            layout.routing_bounds(&blocks),
+            (Vector3(-2, -64, -2), Vector3(7, 12 + TOWER_STEP, 7))
+        );
+    }
+}
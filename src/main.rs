@@ -1,27 +1,84 @@
+mod allocation;
 mod builder;
+mod cache;
 mod compiler;
+mod control;
+mod cse;
+mod dead_code;
 mod file;
+mod grid;
+mod layout;
 mod minecraft;
 mod nbt_reader;
 mod nodes;
 mod parser;
 mod pathfinding;
+mod placement;
+mod redstone;
+mod router;
+mod timing;
 mod vectors;
 
 use builder::build_nodes;
+use cache::RoutingCache;
 use compiler::Compiler;
-use file::read_file_code;
+use file::{read_file_code, write_file_code};
+use layout::LayoutConfig;
 use minecraft::datapack::Datapack;
-use parser::{ast::Statement, parser::parse};
-use pathfinding::Pathfinding;
+use parser::{ast::Statement, formatter::format, parser::parse};
+use pathfinding::{build_failure_markers, build_wires, Pathfinding};
 use std::time::Instant;
+use timing::Timing;
 
 fn main() {
     println!("RedSharp - THIS IS A DEVELOPMENT VERSION.");
+
+    if std::env::args().nth(1).as_deref() == Some("fmt") {
+        format_file();
+        return;
+    }
+
     let ast = parse_file();
 
+    // The code that can never run or change anything is not built
+    let (ast, warnings) = dead_code::eliminate_dead_code(ast);
+    for warning in &warnings {
+        eprintln!("Warning: {warning}");
+    }
+
     let mut compiler = Compiler::new();
     compiler.compile(ast);
+    if !compiler.errors.is_empty() {
+        for error in &compiler.errors {
+            eprintln!("Error: {error}");
+        }
+        eprintln!(
+            "Could not compile the program due to {} error(s)",
+            compiler.errors.len()
+        );
+        std::process::exit(1);
+    }
+
+    // A program whose code was all removed, or without any code, has no structure to build
+    if compiler.nodes.is_empty() {
+        println!("The program computes nothing, there is no circuit to build");
+        return;
+    }
+
+    // An expression written several times is computed once, and its result wired to all its uses
+    let merged = cse::eliminate_common_subexpressions(&mut compiler.nodes, &mut compiler.edges);
+    if merged > 0 {
+        println!("Removed {merged} node(s) computing the same value as another one");
+    }
+
+    // Fewer large structures, for multiplexers driven by the control flow
+    if std::env::args().any(|arg| arg == "--share-structures") {
+        let allocation = allocation::share_structures(&mut compiler.nodes, &mut compiler.edges);
+        println!(
+            "Shared {} operator(s) and {} register(s) with other computations, adding {} multiplexer(s)",
+            allocation.operators, allocation.registers, allocation.muxes
+        );
+    }
 
     // Debug
     // println!("[[ NODES ]]");
@@ -33,19 +90,65 @@ fn main() {
     println!("Generated {} edges", compiler.edges.len());
 
     // Build the nodes and get their ports and obstacles positions
-    let (nodes_instructions, ports_data, mut obstacles) = build_nodes(compiler.nodes.clone());
+    let layout = LayoutConfig::from_args(std::env::args().skip(1)).unwrap_or_else(|error| {
+        eprintln!("Error: {error}");
+        std::process::exit(1);
+    });
+    let (nodes_instructions, ports_data, mut obstacles, structures) =
+        build_nodes(compiler.nodes.clone(), &compiler.edges, &layout);
 
     // Find the edges paths
     println!("Starting the pathfinding...");
-    let mut edges_instructions = Vec::new();
-    let finder = Pathfinding {};
-    finder.resolve(
-        &mut edges_instructions,
+    // With --no-cache, all the wires are routed again and the cache file of the last build is left as it is
+    let use_cache = !std::env::args().any(|arg| arg == "--no-cache");
+    let cache = if use_cache {
+        RoutingCache::load()
+    } else {
+        RoutingCache::default()
+    };
+    let mut finder = Pathfinding::new(layout.router, cache);
+    let (mut wires, failures) = finder.resolve(
         compiler.nodes.clone(),
         compiler.edges.clone(),
         &ports_data,
         &mut obstacles,
+        &structures,
+        &layout,
     );
+    // Without the cache, the next build only routes all the wires again
+    if use_cache {
+        if let Err(error) = finder.cache().save() {
+            eprintln!("Could not write the routing cache: {error}");
+        }
+    }
+
+    // A circuit with missing wires does not work, it is only built to inspect them in the world when asked to
+    let mark_unrouted = std::env::args().any(|arg| arg == "--mark-unrouted");
+    if !failures.is_empty() && !mark_unrouted {
+        eprintln!(
+            "Could not build the circuit due to {} unrouted wire(s). Run with --mark-unrouted to build it with markers on their ports",
+            failures.len()
+        );
+        std::process::exit(1);
+    }
+
+    // Check when the signals arrive at the nodes, and delay the early ones when asked to
+    let mut timing = Timing::new(&compiler.nodes, &compiler.edges, &wires);
+    if std::env::args().any(|arg| arg == "--balance-timing") {
+        let (added, unbalanced) = timing.balance(&mut wires);
+        println!("Added {added} tick(s) of repeaters to balance the inputs of the nodes");
+        for node in unbalanced {
+            println!(
+                "Could not balance the inputs of the {} node, its wires have no room for more repeaters",
+                nodes::NodeType::get_name(compiler.nodes[node].node.clone())
+            );
+        }
+    }
+    timing.print_report(&compiler.nodes, &compiler.edges, &wires);
+
+    let mut edges_instructions = Vec::new();
+    build_wires(&mut edges_instructions, wires);
+    build_failure_markers(&mut edges_instructions, &failures);
 
     // Write the datapack
     println!("Writing the datapack...");
@@ -65,7 +168,17 @@ fn parse_file() -> Vec<Statement> {
     println!(">>> Running: '{}'", program);
 
     let ast_start_time = Instant::now();
-    let ast = parse(&program).unwrap();
+    let (ast, errors) = parse(&program);
+    if !errors.is_empty() {
+        for error in &errors {
+            eprintln!("Error: {error}");
+        }
+        eprintln!(
+            "Could not compile the program due to {} error(s)",
+            errors.len()
+        );
+        std::process::exit(1);
+    }
     println!(
         "AST parsed in {:.9}ms",
         ast_start_time.elapsed().as_nanos() as f64 / 1_000_000.0
@@ -74,3 +187,25 @@ fn parse_file() -> Vec<Statement> {
     // println!("{:#?}", ast);
     ast
 }
+
+/// `redsharp fmt`: formats the program file in place
+fn format_file() {
+    let program = read_file_code().unwrap();
+
+    match format(&program) {
+        Ok(formatted) => {
+            write_file_code(&formatted).unwrap();
+            println!("Formatted main.redstone");
+        }
+        Err(errors) => {
+            for error in &errors {
+                eprintln!("Error: {error}");
+            }
+            eprintln!(
+                "Could not format the program due to {} error(s)",
+                errors.len()
+            );
+            std::process::exit(1);
+        }
+    }
+}
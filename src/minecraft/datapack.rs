@@ -17,10 +17,7 @@ impl Datapack {
     }
 
     pub fn write_generate(&self) {
-        let instructions = vec![
-            "function redsharp:nodes",
-            "function redsharp:edges"
-        ];
+        let instructions = ["function redsharp:nodes", "function redsharp:edges"];
 
         self.write_file(
             Path::new(".\\redsharp\\data\\redsharp\\function\\generate.mcfunction").to_path_buf(),
@@ -29,7 +26,7 @@ impl Datapack {
         .expect("Failed to write datapack generate file");
     }
 
-    pub fn write_nodes(&self, instructions: &Vec<String>) {
+    pub fn write_nodes(&self, instructions: &[String]) {
         self.write_file(
             Path::new(".\\redsharp\\data\\redsharp\\function\\nodes.mcfunction").to_path_buf(),
             instructions.join("\n").as_bytes(),
@@ -37,7 +34,7 @@ impl Datapack {
         .expect("Failed to write datapack nodes file");
     }
 
-    pub fn write_edges(&self, instructions: &Vec<String>) {
+    pub fn write_edges(&self, instructions: &[String]) {
         self.write_file(
             Path::new(".\\redsharp\\data\\redsharp\\function\\edges.mcfunction").to_path_buf(),
             instructions.join("\n").as_bytes(),
@@ -55,7 +52,7 @@ impl Datapack {
 
     fn write_file(&self, path: PathBuf, content: &[u8]) -> io::Result<()> {
         let mut file = fs::File::create(path)?;
-        file.write(content)?;
+        file.write_all(content)?;
         Ok(())
     }
 
@@ -1,10 +1,12 @@
 use crate::nbt_reader::nbt_reader::NbtReader;
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
+use std::collections::HashMap;
 use std::io::Cursor;
 
 pub const PALETTE_SIGN_NAME: &str = "minecraft:oak_wall_sign";
 pub const PALETTE_AIR_NAME: &str = "minecraft:air";
+pub const PALETTE_REPEATER_NAME: &str = "minecraft:repeater";
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct MinecraftStructureSignFrontTextNbt {
@@ -29,6 +31,8 @@ pub struct MinecraftStructureBlockNbt {
 #[serde(rename_all = "PascalCase")]
 pub struct MinecraftStructurePaletteNbt {
     pub name: String,
+    /// The block state, like the facing and the delay of a repeater
+    pub properties: Option<HashMap<String, String>>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -42,7 +46,7 @@ pub struct MinecraftStructureNbt {
 pub fn read_minecraft_structure_file(path: &str) -> MinecraftStructureNbt {
     let reader = NbtReader::new();
 
-    let data = reader.read_nbt_file(&path);
+    let data = reader.read_nbt_file(path);
     let mut cursor = Cursor::new(data.unwrap());
 
     match reader.parse_nbt(&mut cursor) {
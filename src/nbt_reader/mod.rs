@@ -1,3 +1,4 @@
+#[allow(clippy::module_inception)]
 pub mod nbt_reader;
 pub mod nbt_value;
 pub mod tags;
@@ -56,7 +56,11 @@ impl NbtReader {
     }
 
     /// Read and parse the tag payload
-    pub fn read_tag_payload<R: Read>(&self, tag_type: TagType, reader: &mut R) -> io::Result<NbtValue> {
+    pub fn read_tag_payload<R: Read>(
+        &self,
+        tag_type: TagType,
+        reader: &mut R,
+    ) -> io::Result<NbtValue> {
         match tag_type {
             TagType::Byte => {
                 let value = reader.read_i8()?;
@@ -165,8 +169,9 @@ impl NbtReader {
         let mut name_buffer = vec![0; name_length as usize];
 
         reader.read_exact(&mut name_buffer)?;
-        let name = String::from_utf8(name_buffer).unwrap_or_else(|_| String::from("Invalid UTF-8 string"));
+        let name =
+            String::from_utf8(name_buffer).unwrap_or_else(|_| String::from("Invalid UTF-8 string"));
 
         Ok((tag_type, name))
     }
-}
\ No newline at end of file
+}
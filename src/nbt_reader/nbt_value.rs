@@ -1,5 +1,5 @@
-use std::collections::BTreeMap;
 use serde::Serialize;
+use std::collections::BTreeMap;
 
 #[derive(Debug, Serialize)]
 #[serde(untagged)]
@@ -16,4 +16,4 @@ pub enum NbtValue {
     Compound(BTreeMap<String, NbtValue>),
     IntArray(Vec<i32>),
     LongArray(Vec<i64>),
-}
\ No newline at end of file
+}
@@ -35,4 +35,4 @@ impl From<u8> for TagType {
             other => TagType::Unknown(other),
         }
     }
-}
\ No newline at end of file
+}
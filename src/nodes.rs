@@ -19,20 +19,44 @@ impl Port {
     }
 }
 
+#[derive(Debug, Clone)]
+/// A wire bus between an output port and an input port.
+/// The shift moves the bits along the bus: with a shift of 1, the output bit 0 is connected to the input bit 1
+pub struct Edge {
+    pub from_node: String,
+    pub from_port: String,
+    pub to_node: String,
+    pub to_port: String,
+    pub shift: i32,
+    /// The output bits connected. Shifting a value one way then the other keeps the bits shifted out at 0
+    pub mask: u32,
+}
+
 #[derive(Debug, Clone)]
 pub enum NodeType {
     // Secondary nodes
     Operator(Operator),
-    VarInt { name: String, value: u32 },
+    /// The storage of a variable. Its value is written through the data and write enable inputs, and every use of
+    /// the variable is wired from its read output
+    Register {
+        name: String,
+        value: u32,
+        /// The size in bits of the variable, the structure always stores 8 bits
+        width: u32,
+    },
     Int(u32),
-    IntRef,
 
     // Primary nodes
+    /// The button running the program: it gives the control signal of the first step
     Start,
-    End,
-    If,
-    Loop,
+    /// Delays the control signal of a step to the next one, once its values are computed and written
     Wait,
+    /// Selects one of its data inputs, the one whose select input is powered by the control signal of its step. It
+    /// feeds a structure shared by several computations with the inputs of the one running
+    Mux(usize),
+    /// A write to a register: while the assignment runs, it drives its data output with the value and its write
+    /// enable output. The writes of a register are merged with a wired OR at its inputs, so a write only drives its
+    /// outputs while it runs
     Set,
 }
 
@@ -43,22 +67,28 @@ impl NodeType {
             Self::Start => {
                 vec![]
             }
+            // The control signal running the write on each bit, and the value
             Self::Set => {
-                vec![Port::new(1), Port::new(8)]
+                vec![Port::new(8), Port::new(8)]
+            }
+            Self::Wait => {
+                vec![Port::new(1)]
+            }
+            Self::Operator(Operator::BitNot) => {
+                vec![Port::new(8)]
             }
             Self::Operator { .. } => {
                 vec![Port::new(8), Port::new(8)]
             }
-            Self::VarInt { .. } => {
+            // The data and the write enable
+            Self::Register { .. } => {
                 vec![Port::new(8), Port::new(1)]
             }
             Self::Int(_) => {
                 vec![Port::new(8), Port::new(1)]
             }
-            Self::IntRef => {
-                vec![Port::new(8)]
-            }
-            _ => unimplemented!("inputs list of {t:?}"),
+            // The data inputs, then their select inputs on each bit
+            Self::Mux(inputs) => (0..2 * inputs).map(|_| Port::new(8)).collect(),
         }
     }
 
@@ -69,38 +99,62 @@ impl NodeType {
 
     pub fn get_outputs(t: NodeType) -> Vec<Port> {
         match t {
-            Self::Start => {
+            Self::Start | Self::Wait => {
                 vec![Port::new(1)]
             }
+            // The write enable and the data of the register
             Self::Set => {
                 vec![Port::new(1), Port::new(8)]
             }
             Self::Operator(_) => {
                 vec![Port::new(8)]
             }
-            Self::VarInt { .. } => {
+            // The value read by the uses of the variable
+            Self::Register { .. } => {
                 vec![Port::new(8)]
             }
             Self::Int(_) => {
                 vec![Port::new(8)]
             }
-            Self::IntRef => {
+            Self::Mux(_) => {
                 vec![Port::new(8)]
             }
-            _ => unimplemented!("outputs list of {t:?}"),
+        }
+    }
+
+    /// Ticks between a change of the inputs and the one of the outputs, estimated from the structure files
+    pub fn get_delay(t: NodeType) -> u32 {
+        match t {
+            Self::Start => 0,
+            // Four repeaters of 4 ticks, longer than the pulse of the button
+            Self::Wait => 16,
+            // The carry goes through the 8 bits of the adder, the other operators are a torch per bit
+            Self::Operator(Operator::Add) => 8,
+            Self::Operator(Operator::BitAnd | Operator::BitOr | Operator::BitNot) => 1,
+            Self::Set | Self::Register { .. } | Self::Int(_) => 1,
+            // A gate selecting the input, then one merging them
+            Self::Mux(_) => 2,
+            _ => unimplemented!("delay of {t:?}"),
         }
     }
 
     pub fn get_name(t: NodeType) -> &'static str {
         match t {
             Self::Start => "start",
-            Self::Set => "int",
+            Self::Wait => "wait",
+            Self::Set => "set",
             Self::Operator(op) => match op {
                 Operator::Add => "adder",
+                Operator::BitAnd => "and",
+                Operator::BitOr => "or",
+                Operator::BitNot => "not",
                 _ => unimplemented!("operator {op:?}"),
             },
-            Self::VarInt { .. } => "int",
+            Self::Register { .. } => "int",
             Self::Int(_) => "int",
+            Self::Mux(2) => "mux2",
+            Self::Mux(3) => "mux3",
+            Self::Mux(4) => "mux4",
             _ => unimplemented!("node get name"),
         }
     }
@@ -115,6 +169,10 @@ pub struct Node {
     pub is_primary: bool,
     pub inputs: Vec<Port>,
     pub outputs: Vec<Port>,
+    /// The doc comment of the item, written on a sign next to the structure
+    pub doc: Option<String>,
+    /// Index of the statement the node computes in, in the order they run
+    pub step: usize,
 }
 
 impl Node {
@@ -122,12 +180,11 @@ impl Node {
         Self {
             id: Uuid::new_v4().to_string(),
             node: node.clone(),
-            is_primary: matches!(
-                node,
-                NodeType::If | NodeType::Loop | NodeType::Set | NodeType::Wait
-            ),
+            is_primary: matches!(node, NodeType::Set | NodeType::Wait),
             inputs: NodeType::get_inputs(node.clone()),
             outputs: NodeType::get_outputs(node),
+            doc: None,
+            step: 0,
         }
     }
 
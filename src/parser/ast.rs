@@ -2,10 +2,24 @@ use super::lexer::Token;
 
 #[derive(PartialEq, Debug, PartialOrd, Clone)]
 pub enum Statement {
-    Let(String, Expr),
+    /// The name, the value and the doc comment
+    Let(String, Expr, Option<String>),
     Expression(Expr),
     Block(BlockStatement),
     Return(Expr),
+    /// Only produced when parsing the source to format it
+    Trivia(Trivia),
+}
+
+/// The parts of the source that are not code, but must be kept by the formatter
+#[derive(PartialEq, Debug, PartialOrd, Clone)]
+pub enum Trivia {
+    BlankLine,
+    /// The comment with its delimiters. A trailing comment follows some code on the same line
+    Comment {
+        text: String,
+        trailing: bool,
+    },
 }
 
 pub type BlockStatement = Vec<Statement>;
@@ -15,6 +29,10 @@ pub enum Expr {
     Identifier(String),
     Int {
         value: u32,
+        /// The size in bits given by the type suffix, like the 4 of `5u4`
+        width: Option<u32>,
+        /// The literal as written, with its radix, separators and suffix, printed back by the formatter
+        text: String,
     },
     Bool {
         value: bool,
@@ -41,6 +59,7 @@ pub enum Expr {
         name: String,
         parameters: Vec<String>,
         body: BlockStatement,
+        doc: Option<String>,
     },
     Call {
         left: Box<Expr>,
@@ -86,6 +105,12 @@ pub enum Operator {
     Or,
     Modulo,
     Assign,
+    BitAnd,
+    BitOr,
+    BitXor,
+    BitNot,
+    ShiftLeft,
+    ShiftRight,
 }
 
 impl From<Token<'_>> for Operator {
@@ -107,6 +132,11 @@ impl From<Token<'_>> for Operator {
             Token::Ne => Operator::Ne,
             Token::Not => Operator::Not,
             Token::Assign => Operator::Assign,
+            Token::Ampersand => Operator::BitAnd,
+            Token::Pipe => Operator::BitOr,
+            Token::Tilde => Operator::BitNot,
+            Token::ShiftLeft => Operator::ShiftLeft,
+            Token::ShiftRight => Operator::ShiftRight,
             _ => unimplemented!(
                 "Parsing token {:?} into operator is not implemented.",
                 value
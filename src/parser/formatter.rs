@@ -0,0 +1,447 @@
+use super::ast::{BlockStatement, Expr, Operator, Statement, Trivia};
+use super::parser::parse_with_trivia;
+use super::precedence::Precedence;
+
+type Error = String;
+
+const INDENT: &str = "    ";
+
+/// Formats the program source with the canonical spacing and indentation, keeping the comments.
+/// The formatted source is parsed again to make sure it is the same program
+pub fn format(program: &str) -> Result<String, Vec<Error>> {
+    let (ast, errors) = parse_with_trivia(program);
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+
+    let formatted = print(&ast);
+
+    let (formatted_ast, errors) = parse_with_trivia(&formatted);
+    if !errors.is_empty() || formatted_ast != ast {
+        return Err(vec![
+            "The formatted source is not the same program as the original, this is a formatter bug"
+                .to_string(),
+        ]);
+    }
+
+    Ok(formatted)
+}
+
+/// Prints the AST back to source code
+pub fn print(ast: &BlockStatement) -> String {
+    let mut printer = Printer::new(0);
+    printer.write_statements(ast);
+    printer.out
+}
+
+/// Prints an expression back to source code, to quote it in the diagnostics
+pub fn print_expr(expr: &Expr) -> String {
+    let mut printer = Printer::new(0);
+    printer.write_expr(expr);
+    printer.out
+}
+
+struct Printer {
+    out: String,
+    indent: usize,
+}
+
+impl Printer {
+    fn new(indent: usize) -> Self {
+        Self {
+            out: String::new(),
+            indent,
+        }
+    }
+
+    fn write_indent(&mut self) {
+        for _ in 0..self.indent {
+            self.out.push_str(INDENT);
+        }
+    }
+
+    /// Writes each statement on its own lines
+    fn write_statements(&mut self, statements: &[Statement]) {
+        // The statements are printed first, since the end of a statement depends on how the next one starts
+        let printed: Vec<Option<String>> = statements
+            .iter()
+            .map(|statement| match statement {
+                Statement::Trivia(_) => None,
+                _ => {
+                    let mut printer = Printer::new(self.indent);
+                    printer.write_statement(statement);
+                    Some(printer.out)
+                }
+            })
+            .collect();
+
+        for (i, statement) in statements.iter().enumerate() {
+            let text = match (statement, &printed[i]) {
+                (Statement::Trivia(trivia), _) => {
+                    self.write_trivia(trivia);
+                    continue;
+                }
+                (_, Some(text)) => text,
+                _ => unreachable!(),
+            };
+
+            self.write_indent();
+            self.out.push_str(text);
+
+            // A statement ending with a block does not need a ";", unless the next statement would continue its expression
+            if ends_with_block(statement) {
+                let next = printed[i + 1..].iter().flatten().next();
+                if next.is_some_and(|next| next.starts_with(['(', '[', '-', '~'])) {
+                    self.out.push(';');
+                }
+            }
+
+            self.out.push('\n');
+        }
+    }
+
+    fn write_trivia(&mut self, trivia: &Trivia) {
+        match trivia {
+            Trivia::BlankLine => self.out.push('\n'),
+            Trivia::Comment { text, trailing } => {
+                if *trailing && self.out.ends_with('\n') {
+                    self.out.pop();
+                    self.out.push(' ');
+                } else {
+                    self.write_indent();
+                }
+                self.out.push_str(text);
+                self.out.push('\n');
+            }
+        }
+    }
+
+    fn write_doc(&mut self, doc: &Option<String>) {
+        if let Some(doc) = doc {
+            for line in doc.lines() {
+                if line.is_empty() {
+                    self.out.push_str("///");
+                } else {
+                    self.out.push_str("/// ");
+                    self.out.push_str(line);
+                }
+                self.out.push('\n');
+                self.write_indent();
+            }
+        }
+    }
+
+    /// Writes the statement, without the leading indentation and the final newline
+    fn write_statement(&mut self, statement: &Statement) {
+        match statement {
+            Statement::Let(name, value, doc) => {
+                self.write_doc(doc);
+                self.out.push_str("let ");
+                self.out.push_str(name);
+                self.out.push_str(" = ");
+                self.write_expr(value);
+                self.out.push(';');
+            }
+            Statement::Expression(expr) => {
+                if let Expr::Function { doc, .. } = expr {
+                    self.write_doc(doc);
+                }
+                self.write_expr(expr);
+                if !ends_with_block(statement) {
+                    self.out.push(';');
+                }
+            }
+            Statement::Block(block) => self.write_block(block),
+            Statement::Return(expr) => {
+                self.out.push_str("return ");
+                self.write_expr(expr);
+                self.out.push(';');
+            }
+            Statement::Trivia(trivia) => self.write_trivia(trivia),
+        }
+    }
+
+    fn write_block(&mut self, block: &BlockStatement) {
+        if block.is_empty() {
+            self.out.push_str("{}");
+            return;
+        }
+
+        self.out.push_str("{\n");
+        self.indent += 1;
+        self.write_statements(block);
+        self.indent -= 1;
+        self.write_indent();
+        self.out.push('}');
+    }
+
+    fn write_list(&mut self, values: &[Expr]) {
+        for (i, value) in values.iter().enumerate() {
+            if i > 0 {
+                self.out.push_str(", ");
+            }
+            self.write_expr(value);
+        }
+    }
+
+    /// Writes the expression, with parentheses if its precedence is not higher than `min`
+    fn write_operand(&mut self, expr: &Expr, min: Precedence) {
+        if expr_precedence(expr) <= min {
+            self.out.push('(');
+            self.write_expr(expr);
+            self.out.push(')');
+        } else {
+            self.write_expr(expr);
+        }
+    }
+
+    fn write_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Identifier(name) => self.out.push_str(name),
+            Expr::Int { text, .. } => self.out.push_str(text),
+            Expr::Bool { value } => self.out.push_str(&value.to_string()),
+            Expr::String { value } => {
+                self.out.push('"');
+                self.out.push_str(value);
+                self.out.push('"');
+            }
+            Expr::Prefix { operator, right } => {
+                self.out.push_str(prefix_symbol(operator));
+                self.write_operand(right, Precedence::Prefix);
+            }
+            Expr::Infix {
+                left,
+                operator,
+                right,
+            } => {
+                // The left operand is parsed first, so it only needs parentheses if its precedence is lower
+                let operator_precedence = operator.precedence();
+                if expr_precedence(left) < operator_precedence {
+                    self.out.push('(');
+                    self.write_expr(left);
+                    self.out.push(')');
+                } else {
+                    self.write_expr(left);
+                }
+
+                self.out.push(' ');
+                self.out.push_str(infix_symbol(operator));
+                self.out.push(' ');
+                self.write_operand(right, operator_precedence);
+            }
+            Expr::Assignment { left, right } => {
+                self.write_expr(left);
+
+                // `a = a + b` is written `a += b`. The value of a compound assignment is parsed with the lowest precedence
+                if let Expr::Infix {
+                    left: operand,
+                    operator,
+                    right: value,
+                } = right.as_ref()
+                {
+                    let is_variable = matches!(left.as_ref(), Expr::Identifier(_));
+                    if is_variable && operand == left && is_compound_operator(operator) {
+                        self.out.push(' ');
+                        self.out.push_str(infix_symbol(operator));
+                        self.out.push_str("= ");
+                        self.write_expr(value);
+                        return;
+                    }
+                }
+
+                self.out.push_str(" = ");
+                self.write_operand(right, Precedence::Assign);
+            }
+            Expr::Break => self.out.push_str("break"),
+            Expr::Loop { body } => {
+                self.out.push_str("loop ");
+                self.write_block(body);
+            }
+            Expr::If {
+                condition,
+                consequence,
+                alternative,
+            } => {
+                self.out.push_str("if ");
+                self.write_expr(condition);
+                self.out.push(' ');
+                self.write_block(consequence);
+
+                if let Some(alternative) = alternative {
+                    self.out.push_str(" else ");
+                    match &alternative[..] {
+                        [Statement::Expression(else_if @ Expr::If { .. })] => {
+                            self.write_expr(else_if)
+                        }
+                        _ => self.write_block(alternative),
+                    }
+                }
+            }
+            Expr::Function {
+                name,
+                parameters,
+                body,
+                ..
+            } => {
+                self.out.push_str("fn");
+                if !name.is_empty() {
+                    self.out.push(' ');
+                    self.out.push_str(name);
+                }
+                self.out.push('(');
+                self.out.push_str(&parameters.join(", "));
+                self.out.push_str(") ");
+                self.write_block(body);
+            }
+            Expr::Call { left, arguments } => {
+                self.write_expr(left);
+                self.out.push('(');
+                self.write_list(arguments);
+                self.out.push(')');
+            }
+            Expr::Member {
+                left,
+                right,
+                computed,
+            } => {
+                self.write_expr(left);
+                self.out.push('.');
+                match (right.as_ref(), computed) {
+                    (Expr::String { value }, false) => self.out.push_str(value),
+                    _ => {
+                        self.out.push('[');
+                        self.write_expr(right);
+                        self.out.push(']');
+                    }
+                }
+            }
+            Expr::Array { values } => {
+                self.out.push('[');
+                self.write_list(values);
+                self.out.push(']');
+            }
+            Expr::Index { left, index } => {
+                self.write_expr(left);
+                self.out.push('[');
+                self.write_expr(index);
+                self.out.push(']');
+            }
+        }
+    }
+}
+
+/// Whether the printed statement ends with a "}"
+fn ends_with_block(statement: &Statement) -> bool {
+    matches!(
+        statement,
+        Statement::Expression(Expr::If { .. } | Expr::Loop { .. } | Expr::Function { .. })
+    )
+}
+
+/// The precedence of the expression operator. The expressions without operator are never split by the parser
+fn expr_precedence(expr: &Expr) -> Precedence {
+    match expr {
+        Expr::Infix { operator, .. } => operator.precedence(),
+        Expr::Prefix { .. } => Precedence::Prefix,
+        Expr::Assignment { .. } => Precedence::Assign,
+        _ => Precedence::Index,
+    }
+}
+
+/// Whether `a = a <op> b` can be written `a <op>= b`
+fn is_compound_operator(operator: &Operator) -> bool {
+    !matches!(
+        operator,
+        Operator::Gt
+            | Operator::Gte
+            | Operator::Lt
+            | Operator::Lte
+            | Operator::Eq
+            | Operator::Ne
+            | Operator::Assign
+    )
+}
+
+fn prefix_symbol(operator: &Operator) -> &'static str {
+    match operator {
+        Operator::Not => "!",
+        Operator::BitNot => "~",
+        Operator::Negate | Operator::Subtract => "-",
+        _ => unreachable!("{operator:?} is not a prefix operator"),
+    }
+}
+
+fn infix_symbol(operator: &Operator) -> &'static str {
+    match operator {
+        Operator::Add => "+",
+        Operator::Subtract => "-",
+        Operator::Multiply => "*",
+        Operator::Divide => "/",
+        Operator::Power => "^",
+        Operator::Modulo => "%",
+        Operator::Gt => ">",
+        Operator::Gte => ">=",
+        Operator::Lt => "<",
+        Operator::Lte => "<=",
+        Operator::Eq => "==",
+        Operator::Ne => "!=",
+        Operator::And => "&&",
+        Operator::Or => "||",
+        Operator::Assign => "=",
+        Operator::BitAnd => "&",
+        Operator::BitOr => "|",
+        Operator::BitXor => "~",
+        Operator::ShiftLeft => "<<",
+        Operator::ShiftRight => ">>",
+        Operator::Not | Operator::Negate | Operator::BitNot => {
+            unreachable!("{operator:?} is not an infix operator")
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parser::parse;
+
+    /// Formats the source, and checks the round trip: the formatted source gives the same AST, with and without
+    /// the comments, and formatting it again changes nothing
+    fn round_trip(source: &str) -> String {
+        let formatted = format(source).unwrap_or_else(|errors| panic!("{errors:?}"));
+        assert_eq!(format(&formatted), Ok(formatted.clone()));
+
+        let (ast, errors) = parse(source);
+        assert!(errors.is_empty(), "{errors:?}");
+        let (printed, errors) = parse(&print(&ast));
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(printed, ast);
+
+        formatted
+    }
+
+    #[test]
+    fn integer_literals_keep_their_form() {
+        assert_eq!(
+            round_trip("let a=0x0F;let b = 0b0000_1010;\nlet c=5u4;let d = 1_000u16;let e=0xFFu8;a+42;"),
+            "let a = 0x0F;\nlet b = 0b0000_1010;\nlet c = 5u4;\nlet d = 1_000u16;\nlet e = 0xFFu8;\na + 42;\n"
+        );
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_kept() {
+        let source = "// Line comment\nlet i = 0;   // Trailing comment\n\n\n\n/* Block /* nested */ comment */\n/// Doc comment\n///\n/// on several lines\nlet j = 1;\n{\n\n    // In a block\n    i + j;\n}\n//// Not a doc comment\n/// Not documenting an item\ni;\n";
+        assert_eq!(
+            round_trip(source),
+            "// Line comment\nlet i = 0; // Trailing comment\n\n/* Block /* nested */ comment */\n/// Doc comment\n///\n/// on several lines\nlet j = 1;\n{\n    // In a block\n    i + j;\n}\n//// Not a doc comment\n/// Not documenting an item\ni;\n"
+        );
+    }
+
+    #[test]
+    fn statements_are_indented_and_spaced() {
+        let source = "fn f(a,b){return a&b}\nlet i=f(1,2);\nif i>2{i=~i<<1}else{i+=1}\nloop{break}\nlet x = (i | 3) ~ i;\n";
+        assert_eq!(
+            round_trip(source),
+            "fn f(a, b) {\n    return a & b;\n}\nlet i = f(1, 2);\nif i > 2 {\n    i = ~i << 1;\n} else {\n    i += 1;\n}\nloop {\n    break;\n}\nlet x = (i | 3) ~ i;\n"
+        );
+    }
+}
@@ -6,6 +6,15 @@ pub enum Token<'a> {
 
     // Types
     Int(&'a str),
+    /// A number with a fractional part, which the language does not support
+    Float(&'a str),
+    /// The text between the quotes, with its escapes
+    String(&'a str),
+
+    /// The text of a "///" comment
+    DocComment(&'a str),
+    /// A regular comment with its delimiters, only produced by a tokenizer keeping the comments
+    Comment(&'a str),
 
     // Keywords
     If,
@@ -13,6 +22,8 @@ pub enum Token<'a> {
     Else,
     Return,
     Func,
+    Loop,
+    Break,
     True,
     False,
 
@@ -41,6 +52,16 @@ pub enum Token<'a> {
     Percent,
     /// "="
     Assign,
+    /// "&"
+    Ampersand,
+    /// "|"
+    Pipe,
+    /// "~"
+    Tilde,
+    /// "<<"
+    ShiftLeft,
+    /// ">>"
+    ShiftRight,
 
     // Punctuations
     Semicolon,
@@ -62,6 +83,10 @@ pub enum Token<'a> {
 
     /// Unknown token
     Unknown,
+    /// Malformed source, with the error. The parser reports it and skips the token
+    Error(&'static str),
+    /// End of the input
+    Eof,
 }
 
 impl<'a> From<&'a str> for Token<'a> {
@@ -72,6 +97,8 @@ impl<'a> From<&'a str> for Token<'a> {
             "return" => Token::Return,
             "else" => Token::Else,
             "fn" => Token::Func,
+            "loop" => Token::Loop,
+            "break" => Token::Break,
             "true" => Token::True,
             "false" => Token::False,
             _ => Token::Identifier(value),
@@ -80,21 +107,54 @@ impl<'a> From<&'a str> for Token<'a> {
 }
 
 /// The language tokenizer
+#[derive(Clone)]
 pub struct Tokenizer<'a> {
     pos: usize,
     input: &'a str,
     chars: Chars<'a>,
+    /// Produce `Token::Comment` instead of skipping the comments
+    keep_comments: bool,
+    /// Newlines skipped since the last token
+    newlines: usize,
+    /// Newlines skipped before the last token
+    newlines_before: usize,
 }
 
 impl<'a> Tokenizer<'a> {
-    pub fn new(input: &str) -> Tokenizer {
+    pub fn new(input: &str) -> Tokenizer<'_> {
         Tokenizer {
             pos: 0,
             input,
             chars: input.chars(),
+            keep_comments: false,
+            newlines: 0,
+            newlines_before: 0,
+        }
+    }
+
+    /// A tokenizer producing the comments, used to format the source
+    pub fn with_comments(input: &str) -> Tokenizer<'_> {
+        Tokenizer {
+            keep_comments: true,
+            ..Tokenizer::new(input)
         }
     }
 
+    /// Whether the comments are produced as tokens
+    pub fn keeps_comments(&self) -> bool {
+        self.keep_comments
+    }
+
+    /// The number of newlines between the last token and the one before it
+    pub fn newlines_before(&self) -> usize {
+        self.newlines_before
+    }
+
+    /// The line of the current offset, starting at 1
+    pub fn line(&self) -> usize {
+        self.input[..self.pos].matches('\n').count() + 1
+    }
+
     #[inline]
     fn eat(&mut self) -> Option<char> {
         let c = self.chars.next()?;
@@ -125,6 +185,30 @@ impl<'a> Tokenizer<'a> {
         &self.input[from..to]
     }
 
+    /// Skips a "/* ... */" comment, which can be nested. Returns false when the comment is not closed before the end
+    /// of the input
+    fn skip_block_comment(&mut self) -> bool {
+        // Opening "*"
+        self.eat();
+
+        let mut depth = 1;
+        while let Some(c) = self.eat() {
+            if c == '/' && self.peek() == Some('*') {
+                self.eat();
+                depth += 1;
+            } else if c == '*' && self.peek() == Some('/') {
+                self.eat();
+                depth -= 1;
+
+                if depth == 0 {
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
     #[inline]
     fn skip_while(&mut self, mut predicate: impl FnMut(char, bool) -> bool) {
         // It was tried making optimized version of this for eg. line comments, but
@@ -151,24 +235,39 @@ impl<'a> Iterator for Tokenizer<'a> {
             }
 
             // Integers & Floats
-            '0'..='9' => {
-                let mut decimal = false;
-                self.skip_while(|c, _| {
-                    if c.is_ascii_digit() {
-                        return true;
-                    }
+            first @ '0'..='9' => {
+                // The radix prefix, then the digits and "_" separators. The binary literals take all the decimal
+                // digits, so the parser can report a wrong one
+                let is_digit = if first == '0' && self.peek() == Some('x') {
+                    char::is_ascii_hexdigit
+                } else {
+                    char::is_ascii_digit
+                };
+                if first == '0' && matches!(self.peek(), Some('x' | 'b')) {
+                    self.eat();
+                }
+                self.skip_while(|c, _| c == '_' || is_digit(&c));
 
-                    if !decimal && c == '.' {
-                        decimal = true;
-                        return true;
-                    }
+                // The type suffix, such as "u4"
+                let mut next_chars = self.chars.clone();
+                if matches!(next_chars.next(), Some('u' | 'i'))
+                    && next_chars.next().is_some_and(|c| c.is_ascii_digit())
+                {
+                    self.eat();
+                    self.skip_while(|c, _| c.is_ascii_digit());
+                }
 
-                    false
-                });
+                // The fractional part of a float
+                let mut next_chars = self.chars.clone();
+                let decimal = next_chars.next() == Some('.')
+                    && next_chars.next().is_some_and(|c| c.is_ascii_digit());
+                if decimal {
+                    self.eat();
+                    self.skip_while(|c, _| c.is_ascii_digit());
+                }
                 let val = self.read_str(start, self.offset());
                 if decimal {
-                    // Token::Float(val)
-                    panic!("Float are not yet implemented");
+                    Token::Float(val)
                 } else {
                     Token::Int(val)
                 }
@@ -179,13 +278,19 @@ impl<'a> Iterator for Tokenizer<'a> {
                 self.skip_while(|c, esc| c != '"' || esc);
 
                 // skip closing "
-                self.eat()?;
-
-                // Token::String(self.read_str(start + 1, self.offset() - 1))
-                panic!("String are not yet implemented");
+                if self.eat().is_some() {
+                    Token::String(self.read_str(start + 1, self.offset() - 1))
+                } else {
+                    Token::Error("Unterminated string literal, it runs until the end of the file")
+                }
             }
 
-            c if c.is_whitespace() => return self.next(),
+            c if c.is_whitespace() => {
+                if c == '\n' {
+                    self.newlines += 1;
+                }
+                return self.next();
+            }
 
             // Multi-char tokens:
             '=' => {
@@ -205,6 +310,8 @@ impl<'a> Iterator for Tokenizer<'a> {
             '<' => {
                 if self.peek() == Some('=') {
                     Token::Lte
+                } else if self.peek() == Some('<') {
+                    Token::ShiftLeft
                 } else {
                     Token::Lt
                 }
@@ -212,20 +319,49 @@ impl<'a> Iterator for Tokenizer<'a> {
             '>' => {
                 if self.peek() == Some('=') {
                     Token::Gte
+                } else if self.peek() == Some('>') {
+                    Token::ShiftRight
                 } else {
                     Token::Gt
                 }
             }
             '/' => {
                 if self.peek() == Some('/') {
-                    self.skip_while(|c, _| c != '\n');
-                    return self.next();
+                    self.eat();
+
+                    // "///" starts a doc comment, but "////" is a regular comment
+                    let mut next_chars = self.chars.clone();
+                    if next_chars.next() == Some('/') && next_chars.next() != Some('/') {
+                        self.eat();
+                        let text_start = self.offset();
+                        self.skip_while(|c, _| c != '\n');
+                        Token::DocComment(self.read_str(text_start, self.offset()))
+                    } else {
+                        self.skip_while(|c, _| c != '\n');
+                        if !self.keep_comments {
+                            return self.next();
+                        }
+                        Token::Comment(self.read_str(start, self.offset()))
+                    }
+                } else if self.peek() == Some('*') {
+                    if !self.skip_block_comment() {
+                        Token::Error(
+                            "Unterminated block comment, it runs until the end of the file",
+                        )
+                    } else if !self.keep_comments {
+                        return self.next();
+                    } else {
+                        Token::Comment(self.read_str(start, self.offset()))
+                    }
                 } else {
                     Token::Slash
                 }
             }
             '&' if self.peek() == Some('&') => Token::And,
             '|' if self.peek() == Some('|') => Token::Or,
+            '&' => Token::Ampersand,
+            '|' => Token::Pipe,
+            '~' => Token::Tilde,
             ';' => Token::Semicolon,
             ':' => Token::Colon,
             ',' => Token::Comma,
@@ -247,12 +383,51 @@ impl<'a> Iterator for Tokenizer<'a> {
         // If we parsed a multi-char token,
         // eat iterator appropriate number of times
         match token {
-            Token::Eq | Token::Ne | Token::Gte | Token::Lte | Token::And | Token::Or => {
-                self.eat()
-            }
+            Token::Eq
+            | Token::Ne
+            | Token::Gte
+            | Token::Lte
+            | Token::And
+            | Token::Or
+            | Token::ShiftLeft
+            | Token::ShiftRight => self.eat(),
             _ => None,
         };
 
+        self.newlines_before = std::mem::take(&mut self.newlines);
         Some(token)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(input: &str) -> Vec<Token<'_>> {
+        Tokenizer::new(input).collect()
+    }
+
+    #[test]
+    fn integer_literals() {
+        assert_eq!(
+            tokens("42 0x2A 0b0010_1010 5u4 0xFFu8"),
+            [
+                Token::Int("42"),
+                Token::Int("0x2A"),
+                Token::Int("0b0010_1010"),
+                Token::Int("5u4"),
+                Token::Int("0xFFu8"),
+            ]
+        );
+    }
+
+    #[test]
+    fn number_followed_by_an_identifier() {
+        assert_eq!(tokens("5abc"), [Token::Int("5"), Token::Identifier("abc")]);
+        assert_eq!(tokens("5u"), [Token::Int("5"), Token::Identifier("u")]);
+        assert_eq!(
+            tokens("0x1Fg"),
+            [Token::Int("0x1F"), Token::Identifier("g")]
+        );
+    }
+}
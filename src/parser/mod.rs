@@ -1,4 +1,6 @@
 pub mod ast;
+pub mod formatter;
 pub mod lexer;
+#[allow(clippy::module_inception)]
 pub mod parser;
-pub mod precedence;
\ No newline at end of file
+pub mod precedence;
@@ -1,29 +1,133 @@
-use super::ast::{BlockStatement, Expr, Operator, Statement};
+use super::ast::{BlockStatement, Expr, Operator, Statement, Trivia};
 use super::lexer::{Token, Tokenizer};
 use super::precedence::Precedence;
+use std::num::IntErrorKind;
 
 type Error = String;
 
+/// Size in bits of the integers without a type suffix
+const DEFAULT_INT_SIZE: u32 = 8;
+
 struct Parser<'a> {
     tokenizer: Tokenizer<'a>,
     current_token: Token<'a>,
+    /// The errors of the statements skipped during the parsing
+    errors: Vec<Error>,
+    /// The comments and blank lines met since the last statement, when the tokenizer keeps the comments
+    trivia: Vec<Trivia>,
 }
 
 impl<'a> Parser<'a> {
     #[inline]
-    fn new(input: &str) -> Parser {
-        let mut tokenizer = Tokenizer::new(input);
-        let current_token = tokenizer.next().unwrap_or(Token::Unknown);
-
-        Parser {
+    fn new(tokenizer: Tokenizer<'a>) -> Parser<'a> {
+        let mut parser = Parser {
             tokenizer,
-            current_token,
-        }
+            current_token: Token::Eof,
+            errors: Vec::new(),
+            trivia: Vec::new(),
+        };
+        parser.advance();
+        parser
     }
 
     #[inline(always)]
     fn advance(&mut self) {
-        self.current_token = self.tokenizer.next().unwrap_or(Token::Unknown);
+        self.current_token = self.tokenizer.next().unwrap_or(Token::Eof);
+
+        loop {
+            match self.current_token {
+                // The comments are set aside, they are added to the AST before the next statement
+                Token::Comment(text) => self.push_comment(text.trim_end().to_owned()),
+                // A doc comment which does not document an item is a regular comment
+                Token::DocComment(text) if !self.documents_item() => {
+                    if self.tokenizer.keeps_comments() {
+                        self.push_comment(format!("///{}", text.trim_end()));
+                    }
+                }
+                Token::Error(error) => self
+                    .errors
+                    .push(format!("Line {}: {error}", self.tokenizer.line())),
+                _ => return,
+            }
+
+            self.current_token = self.tokenizer.next().unwrap_or(Token::Eof);
+        }
+    }
+
+    fn push_comment(&mut self, text: String) {
+        let newlines = self.tokenizer.newlines_before();
+        if newlines > 1 {
+            self.trivia.push(Trivia::BlankLine);
+        }
+        self.trivia.push(Trivia::Comment {
+            text,
+            trailing: newlines == 0,
+        });
+    }
+
+    /// Whether the doc comment lines starting at the current token are followed by a `let` or `fn` item
+    fn documents_item(&self) -> bool {
+        let mut tokens = self.tokenizer.clone();
+        loop {
+            match tokens.next() {
+                Some(Token::DocComment(_) | Token::Comment(_)) => (),
+                token => return matches!(token, Some(Token::Let | Token::Func)),
+            }
+        }
+    }
+
+    /// Adds the comments and blank lines set aside to the block.
+    /// Blank lines are never added at the start of a block or after another blank line
+    fn push_trivia(&mut self, block: &mut BlockStatement) {
+        for trivia in self.trivia.drain(..) {
+            if trivia == Trivia::BlankLine
+                && matches!(
+                    block.last(),
+                    None | Some(Statement::Trivia(Trivia::BlankLine))
+                )
+            {
+                continue;
+            }
+            block.push(Statement::Trivia(trivia));
+        }
+    }
+
+    /// Skips the tokens until the end of the current statement, so the parsing can continue after an error.
+    /// A "}" is not skipped since it closes the enclosing block
+    fn synchronize(&mut self) {
+        while !matches!(self.current_token, Token::Eof | Token::CloseBrace) {
+            let token = self.current_token;
+            self.advance();
+
+            if token == Token::Semicolon {
+                return;
+            }
+        }
+    }
+
+    /// Parses the statements until the end of the block or of the input.
+    /// A statement with an error is skipped and its error is recorded
+    fn parse_statements(&mut self) -> BlockStatement {
+        let mut block = BlockStatement::with_capacity(8);
+
+        while !matches!(self.current_token, Token::Eof | Token::CloseBrace) {
+            if self.tokenizer.keeps_comments() && self.tokenizer.newlines_before() > 1 {
+                self.trivia.push(Trivia::BlankLine);
+            }
+            self.push_trivia(&mut block);
+
+            match self.parse_statement() {
+                Ok(statement) => block.push(statement),
+                Err(e) => {
+                    self.errors
+                        .push(format!("Line {}: {e}", self.tokenizer.line()));
+                    self.synchronize();
+                }
+            }
+        }
+
+        self.push_trivia(&mut block);
+        block
     }
 
     #[inline]
@@ -45,6 +149,14 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Skips the comma between two list items, unless the list ends with the `end` token
+    fn skip_separator(&mut self, end: Token) -> Result<(), Error> {
+        if self.current_token == end {
+            return Ok(());
+        }
+        self.skip(Token::Comma)
+    }
+
     fn parse_operator(&mut self) -> Operator {
         Operator::from(self.current_token)
     }
@@ -52,7 +164,18 @@ impl<'a> Parser<'a> {
     #[inline]
     fn parse_expr(&mut self, precedence: Precedence) -> Result<Expr, Error> {
         let mut left = match self.current_token {
-            Token::Int(s) => self.parse_int_expression(s),
+            Token::Int(s) => self.parse_int_expression(s)?,
+            Token::Float(literal) => {
+                return Err(format!(
+                    "Float literals are not supported, the numbers are integers. Got {literal:?}"
+                ))
+            }
+            Token::String(value) => {
+                self.advance();
+                Expr::String {
+                    value: value.to_owned(),
+                }
+            }
             Token::True => self.parse_bool_expression(true),
             Token::False => self.parse_bool_expression(false),
             Token::OpenParenthese => {
@@ -62,7 +185,12 @@ impl<'a> Parser<'a> {
                 expr
             }
             Token::If => self.parse_if_expr()?,
-            Token::Not | Token::Minus => self.parse_prefix_expr()?,
+            Token::Loop => self.parse_loop_expr()?,
+            Token::Break => {
+                self.advance();
+                Expr::Break
+            }
+            Token::Not | Token::Minus | Token::Tilde => self.parse_prefix_expr()?,
             Token::Identifier(name) => self.parse_ident(name),
             Token::Func => self.parse_function_expr()?,
             Token::OpenBracket => self.parse_array_expr()?,
@@ -90,6 +218,11 @@ impl<'a> Parser<'a> {
                 | Token::Star
                 | Token::And
                 | Token::Or
+                | Token::Ampersand
+                | Token::Pipe
+                | Token::Tilde
+                | Token::ShiftLeft
+                | Token::ShiftRight
                 | Token::Percent => self.parse_infix_expr(left)?,
                 Token::Dot => self.parse_prop_access_expr(left)?,
                 Token::Assign => self.parse_assign_expr(left)?,
@@ -105,7 +238,8 @@ impl<'a> Parser<'a> {
     #[inline]
     fn parse_statement(&mut self) -> Result<Statement, Error> {
         let statement = match self.current_token {
-            Token::Let => self.parse_declare_statement()?,
+            Token::DocComment(_) => self.parse_documented_statement()?,
+            Token::Let => self.parse_declare_statement(None)?,
             Token::OpenBrace => Statement::Block(self.parse_block_statement()?),
             Token::Return => self.parse_return_statement()?,
             _ => Statement::Expression(self.parse_expr(Precedence::Lowest)?),
@@ -115,7 +249,32 @@ impl<'a> Parser<'a> {
         Ok(statement)
     }
 
-    fn parse_declare_statement(&mut self) -> Result<Statement, Error> {
+    /// Parses the doc comment lines and attach them to the following `let` or `fn` item
+    fn parse_documented_statement(&mut self) -> Result<Statement, Error> {
+        let mut lines = Vec::new();
+        while let Token::DocComment(line) = self.current_token {
+            lines.push(line.trim());
+            self.advance();
+        }
+        let doc = Some(lines.join("\n"));
+
+        match self.current_token {
+            Token::Let => self.parse_declare_statement(doc),
+            Token::Func => {
+                let mut function = self.parse_function_expr()?;
+                if let Expr::Function {
+                    doc: function_doc, ..
+                } = &mut function
+                {
+                    *function_doc = doc;
+                }
+                Ok(Statement::Expression(function))
+            }
+            _ => unreachable!("A doc comment not followed by an item is a regular comment"),
+        }
+    }
+
+    fn parse_declare_statement(&mut self, doc: Option<String>) -> Result<Statement, Error> {
         self.advance();
 
         let identifier = match self.current_token {
@@ -131,7 +290,7 @@ impl<'a> Parser<'a> {
         self.skip(Token::Assign)?;
 
         let value = self.parse_expr(Precedence::Lowest)?;
-        Ok(Statement::Let(identifier, value))
+        Ok(Statement::Let(identifier, value, doc))
     }
 
     fn parse_op_assign_expression(
@@ -152,7 +311,11 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_infix_expr(&mut self, left: Expr) -> Result<Expr, Error> {
-        let operator = self.parse_operator();
+        // "~" is the bitwise NOT as a prefix, but the bitwise XOR between two operands
+        let operator = match self.current_token {
+            Token::Tilde => Operator::BitXor,
+            _ => self.parse_operator(),
+        };
         let precedence = self.current_token.precedence();
         self.advance();
 
@@ -169,13 +332,16 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_prefix_expr(&mut self) -> Result<Expr, Error> {
-        let operator = self.parse_operator();
-        let precedence = self.current_token.precedence();
+        // "-" is the negation as a prefix, but the subtraction between two operands
+        let operator = match self.current_token {
+            Token::Minus => Operator::Negate,
+            _ => self.parse_operator(),
+        };
 
         self.advance();
         Ok(Expr::Prefix {
             operator,
-            right: Box::new(self.parse_expr(precedence)?),
+            right: Box::new(self.parse_expr(Precedence::Prefix)?),
         })
     }
 
@@ -203,6 +369,13 @@ impl<'a> Parser<'a> {
         })
     }
 
+    fn parse_loop_expr(&mut self) -> Result<Expr, Error> {
+        self.advance();
+
+        let body = self.parse_block_statement()?;
+        Ok(Expr::Loop { body })
+    }
+
     fn parse_prop_access_expr(&mut self, left: Expr) -> Result<Expr, Error> {
         match left {
             Expr::Identifier(_) => (),
@@ -268,11 +441,14 @@ impl<'a> Parser<'a> {
     }
 
     #[inline]
-    fn parse_int_expression(&mut self, strval: &str) -> Expr {
+    fn parse_int_expression(&mut self, strval: &str) -> Result<Expr, Error> {
         self.advance();
-        Expr::Int {
-            value: strval.parse().unwrap(),
-        }
+        let (value, width) = parse_int_literal(strval)?;
+        Ok(Expr::Int {
+            value,
+            width,
+            text: strval.to_owned(),
+        })
     }
 
     #[inline]
@@ -299,7 +475,12 @@ impl<'a> Parser<'a> {
             if let Token::Identifier(name) = self.current_token {
                 parameters.push(name.to_owned());
                 self.advance();
-                self.skip_optional(Token::Comma);
+                self.skip_separator(Token::CloseParenthese)?;
+            } else {
+                return Err(format!(
+                    "Expected a parameter name, but got: {:?}",
+                    self.current_token
+                ));
             }
         }
         self.skip(Token::CloseParenthese)?;
@@ -309,6 +490,7 @@ impl<'a> Parser<'a> {
             name: name.to_owned(),
             parameters,
             body,
+            doc: None,
         })
     }
 
@@ -329,10 +511,10 @@ impl<'a> Parser<'a> {
         let mut arguments = vec![];
         while self.current_token != Token::CloseParenthese {
             arguments.push(self.parse_expr(Precedence::Lowest)?);
-            self.skip_optional(Token::Comma);
+            self.skip_separator(Token::CloseParenthese)?;
         }
 
-        self.advance();
+        self.skip(Token::CloseParenthese)?;
 
         Ok(Expr::Call {
             left: Box::new(left),
@@ -346,7 +528,7 @@ impl<'a> Parser<'a> {
         let mut values = Vec::new();
         while self.current_token != Token::CloseBracket {
             values.push(self.parse_expr(Precedence::Lowest)?);
-            self.skip_optional(Token::Comma);
+            self.skip_separator(Token::CloseBracket)?;
         }
 
         self.skip(Token::CloseBracket)?;
@@ -376,26 +558,269 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_block_statement(&mut self) -> Result<BlockStatement, Error> {
-        let mut block = BlockStatement::with_capacity(8);
         self.skip(Token::OpenBrace)?;
+        let block = self.parse_statements();
+        self.skip(Token::CloseBrace)?;
+        Ok(block)
+    }
+}
+
+/// Parses an integer literal such as `42`, `0x2A`, `0b0010_1010` or `42u6`, and checks it fits in its type.
+/// Returns the value and the size given by the type suffix. The compiler checks the size against the structures
+fn parse_int_literal(literal: &str) -> Result<(u32, Option<u32>), Error> {
+    let cleaned = literal.replace('_', "");
+
+    let (digits, radix) = if let Some(digits) = cleaned.strip_prefix("0x") {
+        (digits, 16)
+    } else if let Some(digits) = cleaned.strip_prefix("0b") {
+        (digits, 2)
+    } else {
+        (cleaned.as_str(), 10)
+    };
+
+    if digits.contains('i') {
+        return Err(format!(
+            "Invalid integer literal {literal:?}: the signed integer types are not supported"
+        ));
+    }
 
-        while self.current_token != Token::Unknown && self.current_token != Token::CloseBrace {
-            block.push(self.parse_statement()?);
+    // "u" is not an hexadecimal digit, so it always starts the type suffix
+    let (digits, width) = match digits.split_once('u') {
+        Some((digits, suffix)) => {
+            let width: u32 = suffix
+                .parse()
+                .map_err(|_| format!("Invalid integer type suffix in {literal:?}"))?;
+            if width == 0 || width > u32::BITS {
+                return Err(format!(
+                    "Invalid integer type u{width} in {literal:?}, the size must be between 1 and {} bits",
+                    u32::BITS
+                ));
+            }
+            (digits, Some(width))
         }
+        None => (digits, None),
+    };
+    let size = width.unwrap_or(DEFAULT_INT_SIZE);
+
+    if digits.is_empty() {
+        return Err(format!(
+            "Invalid integer literal {literal:?}: missing digits"
+        ));
+    }
 
-        self.skip(Token::CloseBrace)?;
-        Ok(block)
+    let max = (1u64 << size) - 1;
+    let overflow = || {
+        format!("Integer literal {literal:?} does not fit in {size} bits (the maximum is {max})")
+    };
+
+    let value = u32::from_str_radix(digits, radix).map_err(|e| match e.kind() {
+        IntErrorKind::PosOverflow => overflow(),
+        _ => format!("Invalid integer literal {literal:?}"),
+    })?;
+
+    if value as u64 > max {
+        return Err(overflow());
+    }
+
+    Ok((value, width))
+}
+
+/// Parses the program string into an AST representation.
+/// The parsing continues after an error, so all the errors are returned along with the AST of the valid statements
+pub fn parse(program: &str) -> (BlockStatement, Vec<Error>) {
+    parse_program(Parser::new(Tokenizer::new(program)))
+}
+
+/// Parses the program like `parse`, but also keeps the comments and blank lines as `Statement::Trivia`
+pub fn parse_with_trivia(program: &str) -> (BlockStatement, Vec<Error>) {
+    parse_program(Parser::new(Tokenizer::with_comments(program)))
+}
+
+fn parse_program(mut parser: Parser) -> (BlockStatement, Vec<Error>) {
+    let mut block = parser.parse_statements();
+
+    // A "}" without block stops the statements parsing
+    while parser.current_token == Token::CloseBrace {
+        parser.errors.push(format!(
+            "Line {}: Unexpected token: {:?}",
+            parser.tokenizer.line(),
+            parser.current_token
+        ));
+        parser.advance();
+        block.extend(parser.parse_statements());
     }
+
+    (block, parser.errors)
 }
 
-/// Parses the program string into an AST representation
-pub fn parse(program: &str) -> Result<BlockStatement, Error> {
-    let mut parser = Parser::new(program);
-    let mut block = BlockStatement::new();
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    while parser.current_token != Token::Unknown {
-        block.push(parser.parse_statement()?);
+    fn int(value: u32) -> Expr {
+        Expr::Int {
+            value,
+            width: None,
+            text: value.to_string(),
+        }
     }
 
-    Ok(block)
+    #[test]
+    fn doc_comment_documents_the_following_item() {
+        let (ast, errors) = parse("/// The counter\n/// of the loop\nlet i = 1;");
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(
+            ast,
+            [Statement::Let(
+                "i".to_string(),
+                int(1),
+                Some("The counter\nof the loop".to_string())
+            )]
+        );
+    }
+
+    #[test]
+    fn doc_comment_without_item_is_a_regular_comment() {
+        let program = "i;\n\n/// Not an item\ni;";
+        let i = || Statement::Expression(Expr::Identifier("i".to_string()));
+
+        let (ast, errors) = parse(program);
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(ast, [i(), i()]);
+
+        let (ast, errors) = parse_with_trivia(program);
+        assert!(errors.is_empty(), "{errors:?}");
+        let comment = Trivia::Comment {
+            text: "/// Not an item".to_string(),
+            trailing: false,
+        };
+        assert_eq!(
+            ast,
+            [
+                i(),
+                Statement::Trivia(Trivia::BlankLine),
+                Statement::Trivia(comment),
+                i()
+            ]
+        );
+    }
+
+    #[test]
+    fn unterminated_block_comment_is_an_error() {
+        let (ast, errors) = parse("let i = 1;\n/* /* nested */\nlet j = 2;");
+        assert_eq!(ast, [Statement::Let("i".to_string(), int(1), None)]);
+        assert_eq!(
+            errors,
+            ["Line 3: Unterminated block comment, it runs until the end of the file"]
+        );
+    }
+
+    #[test]
+    fn malformed_inputs_terminate_with_errors() {
+        let inputs = [
+            // The parameter loop of a function, on a token which is not a name
+            "fn f(1) {}",
+            "fn f(a b) {}",
+            "fn f(a",
+            // The argument loop of a call, until the end of the input
+            "f(",
+            "f(1, 2",
+            "f(1 2)",
+            "[1, 2",
+            "let",
+            "let i",
+            "let i =",
+            "{",
+            "}",
+            "if 1 {",
+            "loop {",
+            "i = ;",
+            "1.5;",
+            "let s = \"abc",
+            "/* open",
+            "@ # $",
+        ];
+
+        for input in inputs {
+            for (ast, errors) in [parse(input), parse_with_trivia(input)] {
+                assert!(
+                    !errors.is_empty(),
+                    "{input:?} parsed without error to {ast:?}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn all_the_errors_are_reported() {
+        let (ast, errors) = parse("let = 1;\nlet j = 1.5;\nlet k = 2;\nf(1 2);\nk;");
+        assert_eq!(
+            errors,
+            [
+                "Line 1: Unexpected token. expected an identifier, got a Assign",
+                "Line 2: Float literals are not supported, the numbers are integers. Got \"1.5\"",
+                "Line 4: Expected token Comma, but got: Int(\"2\")",
+            ]
+        );
+        assert_eq!(
+            ast,
+            [
+                Statement::Let("k".to_string(), int(2), None),
+                Statement::Expression(Expr::Identifier("k".to_string())),
+            ]
+        );
+    }
+
+    #[test]
+    fn string_literals() {
+        let (ast, errors) = parse("debug(\"a \\\"b\\\"\");");
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(
+            ast,
+            [Statement::Expression(Expr::Call {
+                left: Box::new(Expr::Identifier("debug".to_string())),
+                arguments: vec![Expr::String {
+                    value: "a \\\"b\\\"".to_string()
+                }],
+            })]
+        );
+    }
+
+    #[test]
+    fn minus_is_a_negation_as_a_prefix() {
+        let (ast, errors) = parse("1 - -2;");
+        assert!(errors.is_empty(), "{errors:?}");
+        assert_eq!(
+            ast,
+            [Statement::Expression(Expr::Infix {
+                left: Box::new(int(1)),
+                operator: Operator::Subtract,
+                right: Box::new(Expr::Prefix {
+                    operator: Operator::Negate,
+                    right: Box::new(int(2)),
+                }),
+            })]
+        );
+    }
+
+    #[test]
+    fn integer_literals() {
+        assert_eq!(parse_int_literal("42"), Ok((42, None)));
+        assert_eq!(parse_int_literal("0x2A"), Ok((42, None)));
+        assert_eq!(parse_int_literal("0b0010_1010"), Ok((42, None)));
+        assert_eq!(parse_int_literal("1_000u16"), Ok((1000, Some(16))));
+        assert_eq!(parse_int_literal("5u4"), Ok((5, Some(4))));
+    }
+
+    #[test]
+    fn integer_literals_must_fit_their_type() {
+        assert!(parse_int_literal("300").is_err());
+        assert!(parse_int_literal("16u4").is_err());
+        assert!(parse_int_literal("99999999999").is_err());
+        assert!(parse_int_literal("5u0").is_err());
+        assert!(parse_int_literal("5u33").is_err());
+        assert!(parse_int_literal("5i4").is_err());
+        assert!(parse_int_literal("0b102").is_err());
+        assert!(parse_int_literal("0x").is_err());
+    }
 }
@@ -1,4 +1,4 @@
-use super::lexer::Token;
+use super::{ast::Operator, lexer::Token};
 
 /// The order here is important
 #[derive(PartialOrd, PartialEq)]
@@ -8,9 +8,14 @@ pub enum Precedence {
     OrAnd,
     Equals,
     LessGreater,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Shift,
     Sum,
     Product,
     Power,
+    Prefix,
     Method,
     Call,
     Index,
@@ -23,6 +28,10 @@ impl Token<'_> {
             Token::Or | Token::And => Precedence::OrAnd,
             Token::Lt | Token::Gt | Token::Lte | Token::Gte => Precedence::LessGreater,
             Token::Eq | Token::Ne => Precedence::Equals,
+            Token::Pipe => Precedence::BitOr,
+            Token::Tilde => Precedence::BitXor,
+            Token::Ampersand => Precedence::BitAnd,
+            Token::ShiftLeft | Token::ShiftRight => Precedence::Shift,
             Token::Plus | Token::Minus => Precedence::Sum,
             Token::Slash | Token::Star | Token::Percent => Precedence::Product,
             Token::Caret => Precedence::Power,
@@ -32,4 +41,24 @@ impl Token<'_> {
             _ => Precedence::Lowest,
         }
     }
-}
\ No newline at end of file
+}
+
+impl Operator {
+    /// The precedence of the operator as an infix, or as a prefix for the unary operators
+    pub fn precedence(&self) -> Precedence {
+        match self {
+            Operator::Assign => Precedence::Assign,
+            Operator::Or | Operator::And => Precedence::OrAnd,
+            Operator::Eq | Operator::Ne => Precedence::Equals,
+            Operator::Lt | Operator::Gt | Operator::Lte | Operator::Gte => Precedence::LessGreater,
+            Operator::BitOr => Precedence::BitOr,
+            Operator::BitXor => Precedence::BitXor,
+            Operator::BitAnd => Precedence::BitAnd,
+            Operator::ShiftLeft | Operator::ShiftRight => Precedence::Shift,
+            Operator::Add | Operator::Subtract => Precedence::Sum,
+            Operator::Multiply | Operator::Divide | Operator::Modulo => Precedence::Product,
+            Operator::Power => Precedence::Power,
+            Operator::Not | Operator::Negate | Operator::BitNot => Precedence::Prefix,
+        }
+    }
+}
@@ -1,158 +1,1286 @@
-use crate::{nodes::Node, vectors::Vector3};
-use pathfinding::directed::astar::astar;
-use std::collections::{HashMap, HashSet};
+use crate::{
+    builder::{sign_messages, StructureBoxes},
+    cache::{layout_hash, RoutingCache},
+    grid::RoutingGrid,
+    layout::LayoutConfig,
+    nodes::{Edge, Node, NodeType},
+    redstone::{WireBlock, MAX_REPEATER_DELAY},
+    router::{Route, Router, RouterKind, SearchBuffers},
+    vectors::Vector3,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    thread,
+    time::Instant,
+};
 
-#[derive(Clone, Debug, Eq)]
-/// Since only the "position" value must be checked by the A* algorithm, some traits are implemented manually to exclude the others values
+/// Signal strength given by an output port to the first block of its wires
+pub const SOURCE_STRENGTH: u8 = 15;
+
+#[derive(Clone, Debug)]
 pub struct PathNode {
     pub position: Vector3,
-    pub steps_from_start: u32,
     pub previous: Option<Vector3>,
+    /// Signal strength of the wire on this block
+    pub strength: u8,
+    /// What the block is made of
+    pub block: WireBlock,
 }
 
-impl PartialEq for PathNode {
-    fn eq(&self, other: &Self) -> bool {
-        self.position == other.position
+impl PathNode {
+    /// Signal strength of the redstone placed on the next block
+    pub fn next_strength(&self) -> u8 {
+        match self.block {
+            WireBlock::Redstone => self.strength.saturating_sub(1),
+            WireBlock::Repeater(_) | WireBlock::Tower(_) => SOURCE_STRENGTH,
+        }
     }
 }
 
-impl std::hash::Hash for PathNode {
-    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
-        self.position.hash(state);
+/// A routed wire: its blocks from the start to the goal, and what each of them is made of
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Wire {
+    pub path: Vec<Vector3>,
+    pub blocks: Vec<WireBlock>,
+}
+
+impl Wire {
+    /// The same wire, moved by the offset
+    fn moved(&self, offset: Vector3) -> Wire {
+        Wire {
+            path: self.path.iter().map(|&pos| pos + offset).collect(),
+            blocks: self.blocks.clone(),
+        }
+    }
+
+    /// The blocks of the wire another wire cannot use without coupling to it
+    pub fn coupled_blocks(&self) -> impl Iterator<Item = Vector3> + '_ {
+        self.path.iter().enumerate().flat_map(|(j, &pos)| {
+            let direction = (j > 0).then(|| pos - self.path[j - 1]);
+            self.blocks[j].coupled_blocks(pos, direction)
+        })
+    }
+
+    /// Ticks the signal takes from the start to the goal
+    pub fn delay(&self) -> u32 {
+        self.blocks.iter().map(|block| block.delay()).sum()
+    }
+
+    /// Signal strength on each block of the wire, from the one on its first block
+    pub fn strengths(&self, first: u8) -> Vec<u8> {
+        let mut strengths = vec![first];
+        for j in 1..self.path.len() {
+            let previous = PathNode {
+                position: self.path[j - 1],
+                previous: None,
+                strength: strengths[j - 1],
+                block: self.blocks[j - 1],
+            };
+            strengths.push(match self.blocks[j] {
+                WireBlock::Redstone => previous.next_strength(),
+                WireBlock::Repeater(_) | WireBlock::Tower(_) => SOURCE_STRENGTH,
+            });
+        }
+        strengths
+    }
+
+    /// Make the signal slower by the ticks, with longer repeaters and new ones on the straight flat blocks after the
+    /// block at `fixed`. Returns the ticks that could not be added
+    pub fn add_delay(&mut self, mut ticks: u32, fixed: usize) -> u32 {
+        // The existing repeaters first, then the redstone, away from the ports
+        for j in fixed + 1..self.path.len() {
+            if let WireBlock::Repeater(delay) = &mut self.blocks[j] {
+                let added = ticks.min(MAX_REPEATER_DELAY - *delay);
+                *delay += added;
+                ticks -= added;
+            }
+        }
+
+        for j in (fixed + 1).max(2)..self.path.len().saturating_sub(2) {
+            if ticks == 0 {
+                break;
+            }
+
+            // A repeater only takes the signal from behind and gives it straight on, on a flat block
+            let direction = self.path[j] - self.path[j - 1];
+            let is_straight = direction == self.path[j + 1] - self.path[j] && direction.1 == 0;
+            let is_after_redstone = self.blocks[j - 1] == WireBlock::Redstone;
+            if self.blocks[j] == WireBlock::Redstone && is_straight && is_after_redstone {
+                let delay = ticks.min(MAX_REPEATER_DELAY);
+                self.blocks[j] = WireBlock::Repeater(delay);
+                ticks -= delay;
+            }
+        }
+
+        ticks
+    }
+
+    /// The positions of wire blocks the wire takes, including the ones of its towers
+    pub fn cells(&self) -> impl Iterator<Item = Vector3> + '_ {
+        self.path
+            .iter()
+            .zip(&self.blocks)
+            .flat_map(|(&pos, block)| block.cells(pos))
     }
 }
 
-pub fn is_obstacle_nearby(obstacles: &HashSet<Vector3>, pos: Vector3) -> bool {
-    for x in -1..=1 {
-        for y in -1..=1 {
-            for z in -1..=1 {
-                if obstacles.contains(&Vector3(pos.0 + x, pos.1 + y, pos.2 + z)) {
-                    return true;
-                }
+/// The wire of one bit of an edge
+#[derive(Clone, Debug)]
+pub struct RoutedWire {
+    pub edge: usize,
+    pub bit: usize,
+    pub wire: Wire,
+    /// Ticks the signal takes from the port to the first block of the wire, through the wires it branches off
+    pub source_delay: u32,
+    /// Index of the last block of the wire other wires branch off. Delaying the blocks up to it would delay them too
+    pub last_branch: usize,
+}
+
+impl RoutedWire {
+    /// Ticks the signal takes from the port to the goal
+    pub fn delay(&self) -> u32 {
+        self.source_delay + self.wire.delay()
+    }
+
+    /// Make the signal slower by the ticks, without slowing down the other wires of the port. Returns the ticks that
+    /// could not be added
+    pub fn add_delay(&mut self, ticks: u32) -> u32 {
+        self.wire.add_delay(ticks, self.last_branch)
+    }
+}
+
+/// A wire that could not be routed
+#[derive(Clone, Debug)]
+pub struct RouteFailure {
+    pub edge: usize,
+    pub bit: usize,
+    /// Number of bits of the edge
+    pub size: usize,
+    pub start: Vector3,
+    pub goal: Vector3,
+    /// The block closest to the goal the search reached, when there is no path at all. Otherwise every path
+    /// crosses other wires
+    pub nearest: Option<Vector3>,
+    /// Id of the nodes whose structures are around the nearest block
+    pub blocking: Vec<String>,
+}
+
+/// A single wire to route: one bit of an edge
+struct Net {
+    edge: usize,
+    bit: usize,
+    /// Number of bits of the edge
+    size: usize,
+    start: Vector3,
+    goal: Vector3,
+    /// The block closest to the goal the last search reached, when it found no path even through the other wires
+    nearest: Option<Vector3>,
+    /// Number of searches that found no path
+    blocked_searches: usize,
+}
+
+impl Net {
+    /// Whether the net is not searched anymore: every search went over the whole grid without reaching the goal
+    fn is_given_up(&self) -> bool {
+        self.blocked_searches >= MAX_BLOCKED_SEARCHES
+    }
+
+    /// Whether the path cell must keep away from the other wires. Next to its ports, a path is allowed to touch other blocks
+    fn needs_clearance(&self, pos: &Vector3) -> bool {
+        pos.distance(&self.start) > 1 && pos.distance(&self.goal) > 1
+    }
+
+    /// Whether the wire can be built as it is, away from the obstacles and the other wires
+    fn is_path_free(&self, wire: &Wire, grid: &RoutingGrid) -> bool {
+        wire.cells().all(|pos| {
+            grid.contains(pos)
+                && (!self.needs_clearance(&pos)
+                    || !grid.is_obstacle_nearby(pos) && grid.usage(&pos) == 0)
+        })
+    }
+
+    /// Whether another wire is too close to the wire
+    fn is_congested(&self, wire: &Wire, grid: &RoutingGrid) -> bool {
+        wire.cells()
+            .any(|pos| self.needs_clearance(&pos) && grid.usage(&pos) > 1)
+    }
+}
+
+/// The wires of one bit of an output port, to each of the inputs it feeds. The first net is routed from the port,
+/// and each next one branches off the wires before it, so the wires make a Steiner tree
+struct Tree {
+    start: Vector3,
+    nets: Vec<usize>,
+    /// The tree of the previous bit of the bus, and the offset to this one, the same at all the ends.
+    /// Its wires run alongside the ones of the previous tree as a ribbon, unless something is in the way
+    follows: Option<(usize, Vector3)>,
+}
+
+impl Tree {
+    /// The routed wires of the tree, in the order they were routed
+    fn wires<'a>(&self, paths: &'a [Option<Wire>]) -> Vec<&'a Wire> {
+        self.nets
+            .iter()
+            .filter_map(|&n| paths[n].as_ref())
+            .collect()
+    }
+
+    /// The wire of the same net in the previous tree, moved to the ports of this one
+    fn ribbon(&self, trees: &[Tree], k: usize, paths: &[Option<Wire>]) -> Option<Wire> {
+        let (previous, offset) = self.follows?;
+        Some(paths[trees[previous].nets[k]].as_ref()?.moved(offset))
+    }
+}
+
+/// The blocks a new wire of the tree can start from, with their signal: the port when no wire is routed yet,
+/// otherwise the flat redstone blocks of the wires, before their goal
+fn tree_sources(start: Vector3, wires: &[&Wire]) -> Vec<PathNode> {
+    let port = PathNode {
+        position: start,
+        previous: None,
+        strength: SOURCE_STRENGTH,
+        block: WireBlock::Redstone,
+    };
+    let mut sources = vec![port];
+
+    for wire in wires {
+        let first = sources
+            .iter()
+            .find(|source| source.position == wire.path[0])
+            .map_or(SOURCE_STRENGTH, |source| source.strength);
+        let strengths = wire.strengths(first);
+
+        for (j, &strength) in strengths
+            .iter()
+            .enumerate()
+            .take(wire.path.len() - 1)
+            .skip(1)
+        {
+            let y = wire.path[j].1;
+            let is_flat = wire.path[j - 1].1 == y && wire.path[j + 1].1 == y;
+            if wire.blocks[j] == WireBlock::Redstone && is_flat {
+                sources.push(PathNode {
+                    position: wire.path[j],
+                    previous: None,
+                    strength,
+                    block: WireBlock::Redstone,
+                });
             }
         }
     }
 
-    false
+    sources
+}
+
+/// Number of blocks of the wires of the tree each cell would couple to. The first block of a wire branching off
+/// the others is the block it branches off, counted once
+fn tree_couplings(wires: &[&Wire]) -> HashMap<Vector3, u32> {
+    let mut couplings = HashMap::new();
+    for (k, wire) in wires.iter().enumerate() {
+        for j in (k > 0) as usize..wire.path.len() {
+            let direction = (j > 0).then(|| wire.path[j] - wire.path[j - 1]);
+            for cell in wire.blocks[j].coupled_blocks(wire.path[j], direction) {
+                *couplings.entry(cell).or_default() += 1;
+            }
+        }
+    }
+    couplings
+}
+
+/// All the wires of the tree as one, so the grid counts the cells they couple to once. Each wire after the first
+/// one starts on a redstone block, so the directions of the repeaters stay right
+fn tree_wire(wires: &[&Wire]) -> Wire {
+    Wire {
+        path: wires.iter().flat_map(|wire| wire.path.clone()).collect(),
+        blocks: wires.iter().flat_map(|wire| wire.blocks.clone()).collect(),
+    }
 }
 
-pub struct Pathfinding {}
+/// Number of rip-up and reroute iterations before giving up on the congested wires
+const MAX_ITERATIONS: usize = 20;
+/// Number of searches of a net without any path before giving up on it. The wires moving away from it between
+/// the iterations can free one, but it is rarely the case and a search without a path goes over the whole grid
+const MAX_BLOCKED_SEARCHES: usize = 2;
+/// Distance from the nearest block of an unrouted wire to the structures reported as blocking it
+const BLOCKING_DISTANCE: u32 = 3;
+
+pub struct Pathfinding {
+    router: Box<dyn Router>,
+    /// The wires of the last build, replaced by the ones routed
+    cache: RoutingCache,
+    /// The memory of the searches of each worker, kept for the next batches
+    buffers: Mutex<Vec<SearchBuffers>>,
+}
 
 impl Pathfinding {
-    fn find_path(
+    pub fn new(router: RouterKind, cache: RoutingCache) -> Self {
+        Self {
+            router: router.router(),
+            cache,
+            buffers: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn cache(&self) -> &RoutingCache {
+        &self.cache
+    }
+
+    /// Find the wires of the tree, one net after the other. A wire of a bus takes the path of the same wire of the
+    /// previous bit when it is free, otherwise it is searched with the cells of that path being cheaper, to only
+    /// leave the ribbon around what is in the way
+    fn route(
         &self,
-        start: Vector3,
-        goal: Vector3,
-        obstacles: &HashSet<Vector3>,
-        entries: &Vec<Vector3>,
-    ) -> Option<Vec<Vector3>> {
-        astar(
-            &PathNode {
-                position: start,
-                steps_from_start: 0,
-                previous: None,
-            },
-            |node| {
-                node.position
-                    .neighbors(&start, &goal, obstacles, entries, &node)
-                    .into_iter()
-                    .map(|(pos, cost)| {
-                        (
-                            PathNode {
-                                position: pos,
-                                steps_from_start: node.steps_from_start + 1,
-                                previous: Some(node.position.clone()),
-                            },
-                            cost,
-                        )
+        trees: &[Tree],
+        tree: &Tree,
+        nets: &[Net],
+        paths: &[Option<Wire>],
+        grid: &RoutingGrid,
+        buffers: &mut SearchBuffers,
+    ) -> Vec<Result<Wire, Vector3>> {
+        let mut routed: Vec<Wire> = Vec::new();
+        let mut results = Vec::new();
+
+        for (k, &net_i) in tree.nets.iter().enumerate() {
+            let net = &nets[net_i];
+            if net.is_given_up() {
+                results.push(Err(net.nearest.unwrap_or(net.goal)));
+                continue;
+            }
+            let wires: Vec<&Wire> = routed.iter().collect();
+            let ribbon = tree.ribbon(trees, k, paths);
+            let mut route = Route {
+                start: tree.start,
+                goal: net.goal,
+                sources: tree_sources(tree.start, &wires),
+                ribbon: HashSet::new(),
+                tree: tree_couplings(&wires),
+            };
+
+            let is_ribbon_free = |ribbon: &Wire| {
+                let source = ribbon.path[0];
+                route.sources.iter().any(|s| s.position == source)
+                    && net.is_path_free(ribbon, grid)
+                    && (1..ribbon.path.len())
+                        .all(|j| !route.is_tree_coupled(ribbon.blocks[j], ribbon.path[j], source))
+            };
+            let result = match ribbon {
+                Some(ribbon) if is_ribbon_free(&ribbon) => Ok(ribbon),
+                ribbon => {
+                    route.ribbon =
+                        ribbon.map_or_else(HashSet::new, |r| r.path.into_iter().collect());
+                    self.router.find_path(&route, grid, buffers)
+                }
+            };
+
+            if let Ok(wire) = &result {
+                routed.push(wire.clone());
+            }
+            results.push(result);
+        }
+
+        results
+    }
+
+    /// Find the wires of the trees. The searches are spread over a worker per available core, each worker taking the
+    /// next tree to route
+    fn find_paths(
+        &self,
+        trees: &[Tree],
+        nets: &[Net],
+        batch: &[usize],
+        paths: &[Option<Wire>],
+        grid: &RoutingGrid,
+    ) -> Vec<Vec<Result<Wire, Vector3>>> {
+        let next = AtomicUsize::new(0);
+        let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+
+        let mut found_paths = Vec::new();
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..cores.min(batch.len()))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut buffers = self.buffers.lock().unwrap().pop().unwrap_or_default();
+                        let mut found = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            let Some(tree) = batch.get(i).map(|&t| &trees[t]) else {
+                                break;
+                            };
+                            let paths = self.route(trees, tree, nets, paths, grid, &mut buffers);
+                            found.push((i, paths));
+                        }
+                        self.buffers.lock().unwrap().push(buffers);
+                        found
                     })
-                    .collect::<Vec<_>>()
-            },
-            |node| node.position.distance(&goal),
-            |node| node.position == goal,
-        )
-        .map(|(path, _cost)| path.into_iter().map(|node| node.position).collect())
+                })
+                .collect();
+
+            for worker in workers {
+                found_paths.extend(worker.join().unwrap());
+            }
+        });
+
+        found_paths.sort_by_key(|(i, _)| *i);
+        found_paths.into_iter().map(|(_, paths)| paths).collect()
     }
 
-    /// Find and build all the paths
-    pub fn resolve(
+    /// Get a net for each bit to connect
+    fn get_nets(
         &self,
-        instructions: &mut Vec<String>,
+        nodes: &[Node],
+        edges: &[Edge],
+        ports: &HashMap<String, Vec<Vector3>>,
+    ) -> Vec<Net> {
+        let mut nets = Vec::new();
+
+        // An edge connects two nodes. An edge can be multiple path e.g. An int (8 bits) corresponds 8 paths
+        for (edge_i, edge) in edges.iter().enumerate() {
+            let node_a = nodes
+                .iter()
+                .find(|n: &&Node| n.id == edge.from_node)
+                .unwrap();
+            let port_a = node_a.inputs.iter().find(|p| p.id == edge.from_port);
+            let port_a = port_a.or(node_a.outputs.iter().find(|p| p.id == edge.from_port));
+            let port_a = port_a.unwrap();
+            let size = port_a.size as usize;
+
+            let pos_a = ports.get(&edge.from_port).unwrap();
+            let pos_b = ports.get(&edge.to_port).unwrap();
+
+            for (i, &start) in pos_a.iter().enumerate().take(size) {
+                // The shifted out bits are not connected, and the missing ones stay at 0
+                let target = i as i32 + edge.shift;
+                if edge.mask >> i & 1 == 0 || target < 0 || target as usize >= pos_b.len() {
+                    continue;
+                }
+                let goal = pos_b[target as usize];
+
+                nets.push(Net {
+                    edge: edge_i,
+                    bit: i,
+                    size,
+                    start,
+                    goal,
+                    nearest: None,
+                    blocked_searches: 0,
+                });
+            }
+        }
+
+        nets
+    }
+
+    /// Group the nets starting from the same output port bit into trees. The bits of a bus are routed as a ribbon
+    /// when the trees go to the same inputs, spaced the same way at all the ends
+    fn get_trees(&self, nets: &[Net]) -> Vec<Tree> {
+        let mut trees: Vec<Tree> = Vec::new();
+        for (net_i, net) in nets.iter().enumerate() {
+            match trees.iter_mut().find(|tree| tree.start == net.start) {
+                Some(tree) => tree.nets.push(net_i),
+                None => trees.push(Tree {
+                    start: net.start,
+                    nets: vec![net_i],
+                    follows: None,
+                }),
+            }
+        }
+
+        // The closest inputs first, the farther ones branching off their wires
+        for tree in &mut trees {
+            tree.nets
+                .sort_by_key(|&n| (nets[n].goal.distance(&tree.start), n));
+        }
+
+        for t in 1..trees.len() {
+            let (previous, tree) = (&trees[t - 1], &trees[t]);
+            let offset = tree.start - previous.start;
+            let is_ribbon = previous.nets.len() == tree.nets.len()
+                && previous.nets.iter().zip(&tree.nets).all(|(&a, &b)| {
+                    nets[a].edge == nets[b].edge && nets[b].goal - nets[a].goal == offset
+                });
+            if is_ribbon {
+                trees[t].follows = Some((t - 1, offset));
+            }
+        }
+
+        trees
+    }
+
+    /// Find all the paths, with a negotiated congestion router.
+    /// On each iteration, the wires sharing cells with other wires are ripped up and routed again, the shared cells
+    /// getting more expensive, until no wire is too close to another one. The wires of an output port bit are
+    /// routed together as a tree. The trees are routed in batches of one tree per thread, each batch seeing the wires
+    /// of the previous ones. A tree following a bus ribbon is routed after the one it follows
+    pub fn resolve(
+        &mut self,
         nodes: Vec<Node>,
-        edges: Vec<(String, String, String, String)>,
+        edges: Vec<Edge>,
         ports: &HashMap<String, Vec<Vector3>>,
         obstacles: &mut HashSet<Vector3>,
-    ) {
+        structures: &StructureBoxes,
+        layout: &LayoutConfig,
+    ) -> (Vec<RoutedWire>, Vec<RouteFailure>) {
         let mut entries: Vec<Vector3> = vec![];
         for v in ports.values() {
             entries.extend(v);
         }
 
-        let mut edge_i = 0;
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let mut nets = self.get_nets(&nodes, &edges, ports);
+        let trees = self.get_trees(&nets);
+        let mut paths: Vec<Option<Wire>> = vec![None; nets.len()];
+        let (min, max) = layout.routing_bounds(obstacles.iter().chain(&entries));
+        let mut grid = RoutingGrid::new(min, max, obstacles, &entries);
 
-        // Loop over the edges and find the paths.
-        // An edge connects two nodes. An edge can be multiple path e.g. An int (8 bits) corresponds 8 paths
-        for edge in edges.clone() {
-            let (node_a_id, port_a_id, _node_b_id, port_b_id) = edge;
+        // The wires of the last build are used again when nothing moved, or when their blocks are still free
+        let ends: Vec<(Vector3, Vector3)> = nets.iter().map(|net| (net.start, net.goal)).collect();
+        let layout_hash = layout_hash(layout.router, obstacles, (min, max), &ends);
+        let is_layout_unchanged = self.cache.is_layout_unchanged(layout_hash);
+        let mut to_route: Vec<usize> = Vec::new();
+        let mut reused = 0;
+        for (tree_i, tree) in trees.iter().enumerate() {
+            let goals: Vec<Vector3> = tree.nets.iter().map(|&n| nets[n].goal).collect();
+            let cached = self.cache.tree(tree.start, &goals).filter(|wires| {
+                is_layout_unchanged
+                    || tree
+                        .nets
+                        .iter()
+                        .zip(wires.iter())
+                        .all(|(&n, wire)| nets[n].is_path_free(wire, &grid))
+            });
+            match cached {
+                Some(wires) => {
+                    grid.add_wire(&tree_wire(&wires.iter().collect::<Vec<_>>()));
+                    for (&net_i, wire) in tree.nets.iter().zip(wires) {
+                        paths[net_i] = Some(wire.clone());
+                    }
+                    reused += wires.len();
+                }
+                None => to_route.push(tree_i),
+            }
+        }
+        if reused > 0 {
+            println!("Reused {reused} wire(s) from the routing cache");
+        }
 
-            let node_a = nodes.iter().find(|n: &&Node| n.id == node_a_id).unwrap();
-            let port_a = node_a.inputs.iter().find(|p| p.id == port_a_id);
-            let port_a = port_a.or(node_a.outputs.iter().find(|p| p.id == port_a_id));
-            let port_a = port_a.unwrap();
-            let size = port_a.size as usize;
+        let routing_start_time = Instant::now();
+        for iteration in 1..=MAX_ITERATIONS {
+            for batch in batches(&trees, &to_route, threads) {
+                for &tree_i in &batch {
+                    let tree = &trees[tree_i];
+                    grid.remove_wire(&tree_wire(&tree.wires(&paths)));
+                    for &net_i in &tree.nets {
+                        paths[net_i] = None;
+                    }
+                }
 
-            let pos_a = ports.get(&port_a_id).unwrap();
-            let pos_b = ports.get(&port_b_id).unwrap();
-
-            for i in 0..size {
-                if let Some(path) = self.find_path(pos_a[i], pos_b[i], obstacles, &entries) {
-                    let mut j = 0;
-                    let mut last_pos = Vector3(0, 0, 0);
-
-                    for pos in path.clone() {
-                        let dir = Vector3(
-                            pos.0 - last_pos.0,
-                            pos.1 - last_pos.1,
-                            pos.2 - last_pos.2,
-                        );
-                        last_pos = pos;
-                        let redstone_step = j % 14;
-
-                        // Build the path and the wires
-                        instructions.push(format!(
-                            "setblock {} {} {} minecraft:green_wool",
-                            pos.0, pos.1, pos.2
-                        ));
-                        instructions.push(format!(
-                            "setblock {} {} {} minecraft:{}",
-                            pos.0,
-                            pos.1 + 1,
-                            pos.2,
-                            if redstone_step == 1 {
-                                let orientation = match dir {
-                                    Vector3(1, _, 0) => "west",
-                                    Vector3(0, _, 1) => "north",
-                                    Vector3(-1, _, 0) => "east",
-                                    Vector3(0, _, -1) => "south",
-                                    _ => unreachable!(),
-                                };
-
-                                format!("repeater[facing={orientation}]")
-                            } else {
-                                "redstone_wire".to_string()
+                let candidates = self.find_paths(&trees, &nets, &batch, &paths, &grid);
+                for (&tree_i, candidates) in batch.iter().zip(candidates) {
+                    let tree = &trees[tree_i];
+                    for (&net_i, candidate) in tree.nets.iter().zip(candidates) {
+                        let net = &mut nets[net_i];
+                        match candidate {
+                            Ok(wire) => {
+                                paths[net_i] = Some(wire);
+                                net.nearest = None;
                             }
-                        ));
+                            Err(_) if net.is_given_up() => {}
+                            Err(nearest) => {
+                                net.nearest = Some(nearest);
+                                net.blocked_searches += 1;
+                            }
+                        }
+                    }
+                    grid.add_wire(&tree_wire(&tree.wires(&paths)));
+                }
+            }
+
+            // A net without any path is routed again too, until giving up on it
+            let needs_rerouting = |net_i: usize, grid: &RoutingGrid| match &paths[net_i] {
+                Some(wire) => nets[net_i].is_congested(wire, grid),
+                None => !nets[net_i].is_given_up(),
+            };
+            let congested: Vec<usize> = (0..trees.len())
+                .filter(|&t| trees[t].nets.iter().any(|&n| needs_rerouting(n, &grid)))
+                .collect();
+            if congested.is_empty() {
+                break;
+            }
+
+            println!(
+                "[ITERATION {iteration}] {} wire tree(s) are too close to other wires or blocked, routing them again",
+                congested.len()
+            );
 
-                        obstacles.insert(pos);
-                        j += 1;
+            for &tree_i in &congested {
+                for &net_i in &trees[tree_i].nets {
+                    let net = &nets[net_i];
+                    for pos in paths[net_i].iter().flat_map(|wire| wire.cells()) {
+                        if net.needs_clearance(&pos) && grid.usage(&pos) > 1 {
+                            grid.add_history(pos);
+                        }
                     }
-                    println!("[EDGE {}/{}] [{}/{size}] Path found!", edge_i + 1, edges.len(), i + 1);
-                } else {
-                    println!("[EDGE {}/{}] [{}/{size}] No path found.", edge_i + 1, edges.len(), i + 1);
                 }
             }
+            grid.next_iteration();
+            to_route = congested;
+        }
+
+        println!(
+            "Routed the wires with the {} router in {:.3}s",
+            self.router.name(),
+            routing_start_time.elapsed().as_secs_f64()
+        );
+
+        // When giving up, only the trees keeping away from the ones already kept are built. The wires of a tree
+        // branch off each other, so a congested tree is left out as a whole
+        grid.clear_wires();
+        let mut unrouted: Vec<usize> = Vec::new();
+        for tree in &trees {
+            for &net_i in &tree.nets {
+                if nets[net_i].nearest.is_some() {
+                    paths[net_i] = None;
+                }
+            }
+
+            let wire = tree_wire(&tree.wires(&paths));
+            grid.add_wire(&wire);
+            let is_congested = tree.nets.iter().any(|&n| {
+                paths[n]
+                    .as_ref()
+                    .is_some_and(|wire| nets[n].is_congested(wire, &grid))
+            });
+            if is_congested {
+                grid.remove_wire(&wire);
+                for &net_i in &tree.nets {
+                    paths[net_i] = None;
+                }
+            }
+            unrouted.extend(tree.nets.iter().filter(|&&n| paths[n].is_none()).copied());
+        }
+        unrouted.sort_unstable();
+
+        // Number of blocks of the bus wires, and how many of them are alongside the previous bit
+        let (mut bus_blocks, mut ribbon_blocks) = (0, 0);
+        for tree in &trees {
+            for (k, &net_i) in tree.nets.iter().enumerate() {
+                if let (Some(wire), Some(ribbon)) = (&paths[net_i], tree.ribbon(&trees, k, &paths))
+                {
+                    let ribbon: HashSet<Vector3> = ribbon.path.into_iter().collect();
+                    bus_blocks += wire.path.len();
+                    ribbon_blocks += wire.path.iter().filter(|pos| ribbon.contains(pos)).count();
+                }
+            }
+        }
+
+        for (net_i, net) in nets.iter().enumerate() {
+            if paths[net_i].is_some() {
+                println!(
+                    "[EDGE {}/{}] [{}/{}] Path found!",
+                    net.edge + 1,
+                    edges.len(),
+                    net.bit + 1,
+                    net.size
+                );
+            }
+        }
+
+        if let Some(share) = (ribbon_blocks * 100).checked_div(bus_blocks) {
+            println!("{share}% of the bus wires run alongside the previous bit");
+        }
+        let branches = trees
+            .iter()
+            .map(|tree| tree.wires(&paths).len().saturating_sub(1))
+            .sum::<usize>();
+        if branches > 0 {
+            println!("{branches} wire(s) branch off the wires of the same output");
+        }
+
+        let failures: Vec<RouteFailure> = unrouted
+            .into_iter()
+            .map(|net_i| {
+                let net = &nets[net_i];
+                let nearest = net.nearest;
+                // The structures around the block the search got stuck at
+                let blocking = nearest.map_or_else(Vec::new, |nearest| {
+                    nodes
+                        .iter()
+                        .filter(|node| {
+                            structures.get(&node.id).is_some_and(|&(min, max)| {
+                                distance_to_box(nearest, min, max) <= BLOCKING_DISTANCE
+                            })
+                        })
+                        .map(|node| node.id.clone())
+                        .collect()
+                });
 
-            edge_i += 1;
+                RouteFailure {
+                    edge: net.edge,
+                    bit: net.bit,
+                    size: net.size,
+                    start: net.start,
+                    goal: net.goal,
+                    nearest,
+                    blocking,
+                }
+            })
+            .collect();
+        print_failures(&failures, &nodes, edges.len(), structures);
+
+        self.cache.update(
+            layout_hash,
+            trees
+                .iter()
+                .filter(|tree| tree.nets.iter().all(|&n| paths[n].is_some()))
+                .map(|tree| tree.wires(&paths).into_iter().cloned().collect())
+                .collect(),
+        );
+
+        let mut wires = Vec::new();
+        for tree in &trees {
+            // Ticks from the port to each block the wires branch off
+            let mut source_delays = HashMap::from([(tree.start, 0)]);
+            let mut taps = Vec::new();
+            for wire in tree.wires(&paths) {
+                taps.push(wire.path[0]);
+                let mut delay = source_delays[&wire.path[0]];
+                for (&pos, block) in wire.path.iter().zip(&wire.blocks).skip(1) {
+                    delay += block.delay();
+                    source_delays.entry(pos).or_insert(delay);
+                }
+            }
+
+            for &net_i in &tree.nets {
+                let Some(wire) = paths[net_i].take() else {
+                    continue;
+                };
+                let net = &nets[net_i];
+                let last_branch = (0..wire.path.len())
+                    .rev()
+                    .find(|&j| taps.contains(&wire.path[j]))
+                    .unwrap_or(0);
+
+                obstacles.extend(wire.cells());
+                wires.push(RoutedWire {
+                    edge: net.edge,
+                    bit: net.bit,
+                    source_delay: source_delays[&wire.path[0]],
+                    last_branch,
+                    wire,
+                });
+            }
         }
+        wires.sort_by_key(|routed| (routed.edge, routed.bit));
+        (wires, failures)
+    }
+}
+
+/// Build the routed wires
+pub fn build_wires(instructions: &mut Vec<String>, wires: Vec<RoutedWire>) {
+    for routed in wires {
+        build_wire(instructions, routed.wire);
+    }
+}
+
+/// Distance from the position to the closest block of the box
+fn distance_to_box(pos: Vector3, min: Vector3, max: Vector3) -> u32 {
+    let closest = Vector3(
+        pos.0.clamp(min.0, max.0),
+        pos.1.clamp(min.1, max.1),
+        pos.2.clamp(min.2, max.2),
+    );
+    pos.distance(&closest)
+}
+
+/// Report the wires that could not be routed, and what is in their way
+fn print_failures(
+    failures: &[RouteFailure],
+    nodes: &[Node],
+    edges: usize,
+    structures: &StructureBoxes,
+) {
+    if failures.is_empty() {
+        return;
+    }
+
+    println!("Could not route {} wire(s):", failures.len());
+    for failure in failures {
+        let Vector3(x, y, z) = failure.start;
+        let Vector3(gx, gy, gz) = failure.goal;
+        println!(
+            "[EDGE {}/{}] [{}/{}] No path found from {x} {y} {z} to {gx} {gy} {gz}",
+            failure.edge + 1,
+            edges,
+            failure.bit + 1,
+            failure.size,
+        );
+
+        let Some(nearest) = failure.nearest else {
+            println!("    Every path crosses other wires");
+            continue;
+        };
+        println!(
+            "    Closest reachable block: {} {} {}, {} block(s) away from the goal",
+            nearest.0,
+            nearest.1,
+            nearest.2,
+            nearest.distance(&failure.goal)
+        );
+        for id in &failure.blocking {
+            let node = nodes.iter().find(|node| &node.id == id).unwrap();
+            let (min, _) = structures[id];
+            println!(
+                "    Blocked by the {} structure at {} {} {}",
+                NodeType::get_name(node.node.clone()),
+                min.0,
+                min.1,
+                min.2
+            );
+        }
+    }
+}
+
+/// Place a red wool block with a sign on the ports of the wires that could not be routed, to find them in the world
+pub fn build_failure_markers(instructions: &mut Vec<String>, failures: &[RouteFailure]) {
+    for failure in failures {
+        let ends = [
+            (failure.start, failure.goal, "to"),
+            (failure.goal, failure.start, "from"),
+        ];
+        for (pos, other, direction) in ends {
+            let text = format!(
+                "Unrouted edge {} bit {} {direction} {} {} {}",
+                failure.edge + 1,
+                failure.bit + 1,
+                other.0,
+                other.1,
+                other.2
+            );
+            instructions.push(format!(
+                "setblock {} {} {} minecraft:red_wool",
+                pos.0, pos.1, pos.2
+            ));
+            instructions.push(format!(
+                "setblock {} {} {} minecraft:oak_sign{{front_text:{{messages:[{}]}}}}",
+                pos.0,
+                pos.1 + 1,
+                pos.2,
+                sign_messages(&text).join(",")
+            ));
+        }
+    }
+}
+
+/// Split the trees to route into batches of one tree per thread. A tree following a bus ribbon needs the paths of
+/// the previous bit, so it starts a new batch when that one is in the current batch
+fn batches(trees: &[Tree], to_route: &[usize], threads: usize) -> Vec<Vec<usize>> {
+    let mut batches: Vec<Vec<usize>> = Vec::new();
+
+    for &tree_i in to_route {
+        let follows = trees[tree_i].follows.map(|(previous, _)| previous);
+        match batches.last_mut() {
+            Some(batch)
+                if batch.len() < threads && !follows.is_some_and(|p| batch.contains(&p)) =>
+            {
+                batch.push(tree_i)
+            }
+            _ => batches.push(vec![tree_i]),
+        }
+    }
+
+    batches
+}
+
+/// Build the path blocks and the redstone, the repeaters or the towers on top of them
+fn build_wire(instructions: &mut Vec<String>, wire: Wire) {
+    let mut last_pos = Vector3(0, 0, 0);
+
+    for (pos, block) in wire.path.into_iter().zip(wire.blocks) {
+        let dir = pos - last_pos;
+        last_pos = pos;
+
+        let mut setblock = |pos: Vector3, block: &str| {
+            instructions.push(format!(
+                "setblock {} {} {} minecraft:{block}",
+                pos.0, pos.1, pos.2
+            ));
+        };
+
+        match block {
+            WireBlock::Redstone => {
+                setblock(pos, "green_wool");
+                setblock(pos + Vector3(0, 1, 0), "redstone_wire");
+            }
+            WireBlock::Repeater(delay) => {
+                let orientation = match dir {
+                    Vector3(1, _, 0) => "west",
+                    Vector3(0, _, 1) => "north",
+                    Vector3(-1, _, 0) => "east",
+                    Vector3(0, _, -1) => "south",
+                    _ => unreachable!(),
+                };
+
+                setblock(pos, "green_wool");
+                setblock(
+                    pos + Vector3(0, 1, 0),
+                    &format!("repeater[facing={orientation},delay={delay}]"),
+                );
+            }
+            // Wool blocks from one above the position to the top of the tower, with a torch on each of them
+            // except the highest one
+            WireBlock::Tower(_) => {
+                let top = block.height() + 1;
+                for y in (1..=top).step_by(2) {
+                    setblock(pos + Vector3(0, y, 0), "green_wool");
+                    if y < top {
+                        setblock(pos + Vector3(0, y + 1, 0), "redstone_torch");
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::ast::Operator, router::AStar};
+    use std::sync::Arc;
+
+    /// The program: edges from ints to bit nots, with the positions of the bits of their ports
+    #[derive(Default)]
+    struct Program {
+        nodes: Vec<Node>,
+        edges: Vec<Edge>,
+        ports: HashMap<String, Vec<Vector3>>,
+    }
+
+    impl Program {
+        fn edge(mut self, from: &[Vector3], to: &[Vector3]) -> Self {
+            let (int, not) = (
+                Node::from(NodeType::Int(0)),
+                Node::from(NodeType::Operator(Operator::BitNot)),
+            );
+            self.ports.insert(int.outputs[0].id.clone(), from.to_vec());
+            self.ports.insert(not.inputs[0].id.clone(), to.to_vec());
+            self.edges.push(Edge {
+                from_node: int.id.clone(),
+                from_port: int.outputs[0].id.clone(),
+                to_node: not.id.clone(),
+                to_port: not.inputs[0].id.clone(),
+                shift: 0,
+                mask: u32::MAX,
+            });
+            self.nodes.extend([int, not]);
+            self
+        }
+
+        /// Another edge from the output of the last edge, to a new bit not
+        fn branch(mut self, to: &[Vector3]) -> Self {
+            let not = Node::from(NodeType::Operator(Operator::BitNot));
+            let last = &self.edges[self.edges.len() - 1];
+            let edge = Edge {
+                from_node: last.from_node.clone(),
+                from_port: last.from_port.clone(),
+                to_node: not.id.clone(),
+                to_port: not.inputs[0].id.clone(),
+                shift: 0,
+                mask: u32::MAX,
+            };
+            self.ports.insert(not.inputs[0].id.clone(), to.to_vec());
+            self.edges.push(edge);
+            self.nodes.push(not);
+            self
+        }
+
+        fn resolve(
+            self,
+            finder: &mut Pathfinding,
+            obstacles: &mut HashSet<Vector3>,
+            structures: &StructureBoxes,
+            layout: &LayoutConfig,
+        ) -> (Vec<RoutedWire>, Vec<RouteFailure>) {
+            finder.resolve(
+                self.nodes,
+                self.edges,
+                &self.ports,
+                obstacles,
+                structures,
+                layout,
+            )
+        }
+    }
+
+    /// A router counting its searches
+    struct Counted(Arc<AtomicUsize>);
+
+    impl Router for Counted {
+        fn name(&self) -> &'static str {
+            "counted"
+        }
+
+        fn find_path(
+            &self,
+            route: &Route,
+            grid: &RoutingGrid,
+            buffers: &mut SearchBuffers,
+        ) -> Result<Wire, Vector3> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            AStar.find_path(route, grid, buffers)
+        }
+    }
+
+    fn line(from: Vector3, to: Vector3) -> Wire {
+        let direction = Vector3((to.0 - from.0).signum(), 0, (to.2 - from.2).signum());
+        let mut path = vec![from];
+        while path[path.len() - 1] != to {
+            path.push(path[path.len() - 1] + direction);
+        }
+        Wire {
+            blocks: vec![WireBlock::Redstone; path.len()],
+            path,
+        }
+    }
+
+    /// Whether a block of the wire, away from its ends, couples to the other wire
+    fn couples_to(wire: &Wire, other: &Wire) -> bool {
+        let coupled: HashSet<Vector3> = other.coupled_blocks().collect();
+        let ends = [wire.path[0], wire.path[wire.path.len() - 1]];
+        wire.cells()
+            .filter(|pos| ends.iter().all(|end| pos.distance(end) > 1))
+            .any(|pos| coupled.contains(&pos))
+    }
+
+    fn layout(min: Vector3, max: Vector3) -> LayoutConfig {
+        LayoutConfig {
+            bounds: Some((min, max)),
+            ..LayoutConfig::default()
+        }
+    }
+
+    #[test]
+    fn signal_decays_until_a_repeater_gives_it_back() {
+        let mut wire = line(Vector3(0, 0, 0), Vector3(20, 0, 0));
+        assert_eq!(wire.strengths(SOURCE_STRENGTH)[..3], [15, 14, 13]);
+        wire.blocks[10] = WireBlock::Repeater(1);
+        // The redstone after the repeater gets its full strength
+        assert_eq!(wire.strengths(SOURCE_STRENGTH)[9..13], [6, 15, 15, 14]);
+
+        // Slowing the signal down makes the repeater longer, then adds new ones on the redstone
+        assert_eq!(wire.add_delay(5, 0), 0);
+        assert_eq!(wire.delay(), 6);
+        assert_eq!(wire.blocks[10], WireBlock::Repeater(MAX_REPEATER_DELAY));
+        assert_eq!(wire.blocks[2], WireBlock::Repeater(2));
+    }
+
+    #[test]
+    fn long_wires_get_repeaters_before_the_signal_dies_out() {
+        let (start, goal) = (Vector3(0, 100, 0), Vector3(40, 100, 0));
+        let mut finder = Pathfinding::new(RouterKind::AStar, RoutingCache::default());
+        let (wires, failures) = Program::default().edge(&[start], &[goal]).resolve(
+            &mut finder,
+            &mut HashSet::new(),
+            &StructureBoxes::new(),
+            &layout(Vector3(-6, 96, -6), Vector3(46, 108, 6)),
+        );
+        assert!(failures.is_empty());
+
+        let wire = &wires[0].wire;
+        assert_eq!(
+            (wire.path[0], wire.path[wire.path.len() - 1]),
+            (start, goal)
+        );
+        assert!(wire
+            .blocks
+            .iter()
+            .any(|block| matches!(block, WireBlock::Repeater(_))));
+        assert!(wire
+            .strengths(SOURCE_STRENGTH)
+            .iter()
+            .all(|&strength| strength > 0));
+    }
+
+    #[test]
+    fn wires_branch_off_the_flat_redstone_of_the_tree() {
+        let start = Vector3(0, 0, 0);
+        let path = [(0, 0), (1, 0), (2, 0), (3, 1), (4, 1), (5, 1)];
+        let first = Wire {
+            path: path.iter().map(|&(x, y)| Vector3(x, y, 0)).collect(),
+            blocks: vec![WireBlock::Redstone; path.len()],
+        };
+        let branch = line(Vector3(4, 1, 0), Vector3(4, 1, -3));
+
+        // Not the goals, nor the blocks on a slope
+        let sources: Vec<(Vector3, u8)> = tree_sources(start, &[&first, &branch])
+            .iter()
+            .map(|source| (source.position, source.strength))
+            .collect();
+        assert_eq!(
+            sources,
+            [
+                (start, 15),
+                (Vector3(1, 0, 0), 14),
+                (Vector3(4, 1, 0), 11),
+                (Vector3(4, 1, -1), 10),
+                (Vector3(4, 1, -2), 9),
+            ]
+        );
+
+        // The block a wire branches off is counted once
+        let first = line(start, Vector3(4, 0, 0));
+        let branch = line(Vector3(2, 0, 0), Vector3(2, 0, -3));
+        assert_eq!(tree_couplings(&[&first])[&Vector3(2, 0, 0)], 3);
+        assert_eq!(tree_couplings(&[&first, &branch])[&Vector3(2, 0, 0)], 4);
+    }
+
+    #[test]
+    fn farther_inputs_branch_off_the_wire_to_the_closest_one() {
+        let start = Vector3(0, 100, 0);
+        let (near, far) = (Vector3(12, 100, 0), Vector3(12, 100, 8));
+        let program = Program::default().edge(&[start], &[near]).branch(&[far]);
+        let mut finder = Pathfinding::new(RouterKind::AStar, RoutingCache::default());
+        let (wires, failures) = program.resolve(
+            &mut finder,
+            &mut HashSet::new(),
+            &StructureBoxes::new(),
+            &layout(Vector3(-6, 96, -6), Vector3(18, 108, 14)),
+        );
+        assert!(failures.is_empty());
+        assert_eq!(wires.len(), 2);
+
+        let (trunk, branch) = (&wires[0], &wires[1]);
+        assert_eq!(trunk.wire.path[0], start);
+        assert_eq!(branch.wire.path[branch.wire.path.len() - 1], far);
+        let tap = trunk
+            .wire
+            .path
+            .iter()
+            .position(|&pos| pos == branch.wire.path[0])
+            .unwrap();
+        assert!(tap > 0);
+
+        // Delaying the trunk before the tap would delay the branch too
+        assert_eq!(trunk.last_branch, tap);
+        let to_tap: u32 = trunk.wire.blocks[1..=tap]
+            .iter()
+            .map(|block| block.delay())
+            .sum();
+        assert_eq!(branch.source_delay, to_tap);
+    }
+
+    #[test]
+    fn search_buffers_are_kept_for_each_worker() {
+        let bits = |x: i32| -> Vec<Vector3> { (0..8).map(|z| Vector3(x, 100, 2 * z)).collect() };
+        let program = Program::default().edge(&bits(0), &bits(24));
+        let mut finder = Pathfinding::new(RouterKind::AStar, RoutingCache::default());
+        let (wires, failures) = program.resolve(
+            &mut finder,
+            &mut HashSet::new(),
+            &StructureBoxes::new(),
+            &LayoutConfig::default(),
+        );
+        assert_eq!(wires.len(), 8);
+        assert!(failures.is_empty());
+
+        // One buffer per worker, not per net
+        let cores = thread::available_parallelism().map_or(1, |cores| cores.get());
+        let buffers = finder.buffers.get_mut().unwrap().len();
+        assert!((1..=cores.min(8)).contains(&buffers));
+    }
+
+    #[test]
+    fn bits_of_a_ribbon_keep_the_spacing_of_their_ports() {
+        let bits = |x: i32| -> Vec<Vector3> { (0..4).map(|z| Vector3(x, 100, 2 * z)).collect() };
+        let program = Program::default().edge(&bits(0), &bits(16));
+        let mut finder = Pathfinding::new(RouterKind::AStar, RoutingCache::default());
+        let (wires, failures) = program.resolve(
+            &mut finder,
+            &mut HashSet::new(),
+            &StructureBoxes::new(),
+            &layout(Vector3(-6, 96, -6), Vector3(22, 108, 12)),
+        );
+        assert!(failures.is_empty());
+        assert_eq!(wires.len(), 4);
+
+        // The ports are 2 blocks apart, so the wires alongside each other do not couple. One block apart, they would
+        for pair in wires.windows(2) {
+            let (wire, next) = (&pair[0].wire, &pair[1].wire);
+            assert_eq!(*next, wire.moved(Vector3(0, 0, 2)));
+            assert!(!couples_to(next, wire));
+            assert!(couples_to(&wire.moved(Vector3(0, 0, 1)), wire));
+        }
+    }
+
+    #[test]
+    fn congested_wires_are_ripped_up_and_routed_apart() {
+        let (a, b) = (
+            (Vector3(0, 100, 0), Vector3(12, 100, 0)),
+            (Vector3(0, 100, 1), Vector3(12, 100, 1)),
+        );
+        let program = Program::default().edge(&[a.0], &[a.1]).edge(&[b.0], &[b.1]);
+        let layout = layout(Vector3(-6, 96, -6), Vector3(18, 108, 8));
+
+        // The wires of the last build run side by side, coupling to each other
+        let mut cache = RoutingCache::default();
+        let hash = layout_hash(
+            layout.router,
+            &HashSet::new(),
+            layout.routing_bounds(&[]),
+            &[a, b],
+        );
+        let (wire_a, wire_b) = (line(a.0, a.1), line(b.0, b.1));
+        assert!(couples_to(&wire_b, &wire_a));
+        cache.update(hash, vec![vec![wire_a.clone()], vec![wire_b.clone()]]);
+
+        let mut finder = Pathfinding::new(RouterKind::AStar, cache);
+        let (wires, failures) = program.resolve(
+            &mut finder,
+            &mut HashSet::new(),
+            &StructureBoxes::new(),
+            &layout,
+        );
+        assert!(failures.is_empty());
+        assert_eq!(wires.len(), 2);
+        let (routed_a, routed_b) = (&wires[0].wire, &wires[1].wire);
+        assert!(*routed_a != wire_a || *routed_b != wire_b);
+        assert!(!couples_to(routed_a, routed_b));
+        assert!(!couples_to(routed_b, routed_a));
+    }
+
+    #[test]
+    fn walled_in_wires_are_given_up_and_reported() {
+        let (start, goal) = (Vector3(0, 100, 0), Vector3(12, 100, 0));
+        let program = Program::default().edge(&[start], &[goal]);
+        let not = program.nodes[1].id.clone();
+        let (min, max) = (Vector3(-6, 96, -6), Vector3(18, 108, 8));
+
+        // The structure of the bit not is a wall across the whole routing volume
+        let wall = (Vector3(6, min.1, min.2), Vector3(6, max.1, max.2));
+        let mut obstacles: HashSet<Vector3> = (min.1..=max.1)
+            .flat_map(|y| (min.2..=max.2).map(move |z| Vector3(6, y, z)))
+            .collect();
+        let structures = StructureBoxes::from([(not.clone(), wall)]);
+
+        let searches = Arc::new(AtomicUsize::new(0));
+        let mut finder = Pathfinding {
+            router: Box::new(Counted(searches.clone())),
+            cache: RoutingCache::default(),
+            buffers: Mutex::new(Vec::new()),
+        };
+        let (wires, failures) =
+            program.resolve(&mut finder, &mut obstacles, &structures, &layout(min, max));
+        assert!(wires.is_empty());
+        assert_eq!(searches.load(Ordering::Relaxed), MAX_BLOCKED_SEARCHES);
+
+        assert_eq!(failures.len(), 1);
+        let failure = &failures[0];
+        assert_eq!((failure.start, failure.goal), (start, goal));
+        assert!(failure.nearest.is_some_and(|nearest| nearest.0 < 6));
+        assert_eq!(failure.blocking, [not]);
     }
 }
@@ -0,0 +1,860 @@
+use crate::{layout::LayoutConfig, vectors::Vector3};
+use std::{collections::HashMap, mem, str::FromStr};
+
+/// Number of moves tried by the simulated annealing for each structure
+const ANNEALING_STEPS_PER_NODE: usize = 500;
+/// Temperature at the end of the annealing, relative to the one at the start
+const FINAL_TEMPERATURE: f64 = 0.001;
+/// Number of bits of wires that can go through a column of the layout as wide as the spacing before it is congested
+const BIN_CAPACITY: u32 = 16;
+/// Cost of each bit of wire over the capacity of a column, in blocks of wire
+const CONGESTION_COST: f64 = 4.0;
+/// Number of passes over the layers ordering the structures of each one after the ones they are connected to
+const ORDERING_SWEEPS: usize = 8;
+
+/// How the structures are arranged before routing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PlacementKind {
+    /// In rows, in the order the nodes were compiled and as the structures were saved
+    Rows,
+    /// In rows, in the order and the orientations the simulated annealing found to have the shortest and least
+    /// congested wires
+    #[default]
+    Annealing,
+    /// In layers along x following the data: each structure in a layer after the ones its inputs come from, the
+    /// control nodes first in each layer so they line up along x, ordered and turned to keep the wires short
+    Layered,
+}
+
+impl FromStr for PlacementKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rows" => Ok(PlacementKind::Rows),
+            "annealing" => Ok(PlacementKind::Annealing),
+            "layered" => Ok(PlacementKind::Layered),
+            _ => Err(format!(
+                "Unknown placement {s:?}, expected \"rows\", \"annealing\" or \"layered\""
+            )),
+        }
+    }
+}
+
+/// Rotation of a structure around the vertical axis, seen from above
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rotation {
+    #[default]
+    None,
+    Clockwise90,
+    Clockwise180,
+    Counterclockwise90,
+}
+
+/// How a structure is turned when placed: mirrored along z first, then rotated, like `place template` does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Orientation {
+    pub rotation: Rotation,
+    pub mirror: bool,
+}
+
+impl Orientation {
+    /// The 8 different orientations. Mirroring along x is the same as mirroring along z and rotating by 180
+    const ALL: [Orientation; 8] = [
+        Orientation {
+            rotation: Rotation::None,
+            mirror: false,
+        },
+        Orientation {
+            rotation: Rotation::Clockwise90,
+            mirror: false,
+        },
+        Orientation {
+            rotation: Rotation::Clockwise180,
+            mirror: false,
+        },
+        Orientation {
+            rotation: Rotation::Counterclockwise90,
+            mirror: false,
+        },
+        Orientation {
+            rotation: Rotation::None,
+            mirror: true,
+        },
+        Orientation {
+            rotation: Rotation::Clockwise90,
+            mirror: true,
+        },
+        Orientation {
+            rotation: Rotation::Clockwise180,
+            mirror: true,
+        },
+        Orientation {
+            rotation: Rotation::Counterclockwise90,
+            mirror: true,
+        },
+    ];
+
+    /// Position of a block of the template from the position the template is placed at, as Minecraft computes it
+    fn transform(self, Vector3(x, y, z): Vector3) -> Vector3 {
+        let z = if self.mirror { -z } else { z };
+        match self.rotation {
+            Rotation::None => Vector3(x, y, z),
+            Rotation::Clockwise90 => Vector3(-z, y, x),
+            Rotation::Clockwise180 => Vector3(-x, y, -z),
+            Rotation::Counterclockwise90 => Vector3(z, y, -x),
+        }
+    }
+
+    /// Lowest corner of the turned structure, from the position the template is placed at
+    fn lowest_corner(self, size: Vector3) -> Vector3 {
+        let a = self.transform(Vector3(0, 0, 0));
+        let b = self.transform(size - Vector3(1, 1, 1));
+        Vector3(a.0.min(b.0), a.1.min(b.1), a.2.min(b.2))
+    }
+
+    /// Position of a block of the template from the lowest corner of the turned structure
+    pub fn apply(self, pos: Vector3, size: Vector3) -> Vector3 {
+        self.transform(pos) - self.lowest_corner(size)
+    }
+
+    /// Facing of a block of the template once the structure is turned, like "north" becoming "east" when rotated
+    /// clockwise
+    pub fn facing(self, facing: &str) -> &'static str {
+        const FACINGS: [(&str, Vector3); 4] = [
+            ("north", Vector3(0, 0, -1)),
+            ("east", Vector3(1, 0, 0)),
+            ("south", Vector3(0, 0, 1)),
+            ("west", Vector3(-1, 0, 0)),
+        ];
+
+        let (_, direction) = FACINGS
+            .iter()
+            .find(|(name, _)| *name == facing)
+            .unwrap_or_else(|| panic!("Unknown facing {facing:?}"));
+        let turned = self.transform(*direction);
+        FACINGS
+            .iter()
+            .find(|(_, direction)| *direction == turned)
+            .unwrap()
+            .0
+    }
+
+    /// Size of the turned structure
+    pub fn size(self, size: Vector3) -> Vector3 {
+        match self.rotation {
+            Rotation::None | Rotation::Clockwise180 => size,
+            Rotation::Clockwise90 | Rotation::Counterclockwise90 => Vector3(size.2, size.1, size.0),
+        }
+    }
+
+    /// Arguments of `place template` putting the lowest corner of the turned structure at the position: where the
+    /// template goes, its rotation and its mirror
+    pub fn place_arguments(self, position: Vector3, size: Vector3) -> String {
+        let Vector3(x, y, z) = position - self.lowest_corner(size);
+        let rotation = match self.rotation {
+            Rotation::None => "none",
+            Rotation::Clockwise90 => "clockwise_90",
+            Rotation::Clockwise180 => "180",
+            Rotation::Counterclockwise90 => "counterclockwise_90",
+        };
+        let mirror = if self.mirror { "left_right" } else { "none" };
+        format!("{x} {y} {z} {rotation} {mirror}")
+    }
+}
+
+/// The wires of an edge between two structures, to estimate their length
+pub struct Connection {
+    pub from: usize,
+    pub to: usize,
+    /// Position of the first bit of the ports in the template of their structure
+    pub from_port: Vector3,
+    pub to_port: Vector3,
+    pub bits: u32,
+}
+
+/// Position of the lowest corner and orientation of each structure, from the sizes of their templates, whether they
+/// are control nodes and the wires between them
+pub fn place(
+    sizes: &[Vector3],
+    controls: &[bool],
+    connections: &[Connection],
+    layout: &LayoutConfig,
+) -> Vec<(Vector3, Orientation)> {
+    if layout.placement == PlacementKind::Layered {
+        return layered(sizes, controls, connections, layout);
+    }
+
+    let placement = Placement {
+        order: (0..sizes.len()).collect(),
+        orientations: vec![Orientation::default(); sizes.len()],
+    };
+    let placement = match layout.placement {
+        PlacementKind::Annealing => anneal(placement, sizes, connections, layout),
+        _ => placement,
+    };
+
+    placement
+        .positions(sizes, layout)
+        .into_iter()
+        .zip(placement.orientations)
+        .collect()
+}
+
+/// Order of the structures in the rows, and how each one is turned
+#[derive(Clone)]
+struct Placement {
+    order: Vec<usize>,
+    orientations: Vec<Orientation>,
+}
+
+impl Placement {
+    fn positions(&self, sizes: &[Vector3], layout: &LayoutConfig) -> Vec<Vector3> {
+        rows(&self.order, &turned(sizes, &self.orientations), layout)
+    }
+}
+
+/// Sizes of the structures once turned
+fn turned(sizes: &[Vector3], orientations: &[Orientation]) -> Vec<Vector3> {
+    sizes
+        .iter()
+        .zip(orientations)
+        .map(|(&size, orientation)| orientation.size(size))
+        .collect()
+}
+
+/// Place the structures in rows along x, in the order, starting a new row when one gets longer than the grid width
+fn rows(order: &[usize], sizes: &[Vector3], layout: &LayoutConfig) -> Vec<Vector3> {
+    let Vector3(origin_x, origin_y, origin_z) = layout.origin;
+
+    // Current node position
+    let mut current_row_start_x = origin_x;
+    let mut current_row_start_z = origin_z;
+    let mut current_row_z = 0;
+
+    let mut positions = vec![layout.origin; sizes.len()];
+    for &node in order {
+        let Vector3(x, _, z) = sizes[node];
+        current_row_z = current_row_z.max(z);
+
+        positions[node] = Vector3(current_row_start_x, origin_y, current_row_start_z);
+
+        // Move to the next cell
+        current_row_start_x += x + layout.spacing;
+
+        // Go to the next row if too far
+        if current_row_start_x - origin_x >= layout.grid_width {
+            current_row_start_x = origin_x;
+            current_row_start_z += current_row_z + layout.spacing;
+            current_row_z = 0;
+        }
+    }
+
+    positions
+}
+
+/// Estimated cost of the wires between the structures, turned and placed at the positions
+fn placed_cost(
+    positions: &[Vector3],
+    orientations: &[Orientation],
+    sizes: &[Vector3],
+    connections: &[Connection],
+    layout: &LayoutConfig,
+) -> f64 {
+    let ports: Vec<Ports> = connections
+        .iter()
+        .map(|connection| connection.ports(positions, orientations, sizes))
+        .collect();
+    WireCost::new(&ports, layout).total()
+}
+
+/// The first bits of the output and the input ports of a wire, and its number of bits
+type Ports = (Vector3, Vector3, u32);
+
+impl Connection {
+    /// The ports of the wire, between the structures turned and placed at the positions
+    fn ports(
+        &self,
+        positions: &[Vector3],
+        orientations: &[Orientation],
+        sizes: &[Vector3],
+    ) -> Ports {
+        let port = |node: usize, port: Vector3| {
+            positions[node] + orientations[node].apply(port, sizes[node])
+        };
+        (
+            port(self.from, self.from_port),
+            port(self.to, self.to_port),
+            self.bits,
+        )
+    }
+}
+
+/// Estimated cost of the wires between the ports of the placed structures, with their number of bits: their total
+/// length, plus the bits going through the columns of the layout already crossed by too many wires. Each wire is
+/// counted in all the columns of the box between its ports. The wires can be moved one at a time, only the columns
+/// they cross being counted again
+struct WireCost<'a> {
+    layout: &'a LayoutConfig,
+    ports: Vec<Ports>,
+    length: u32,
+    /// Number of bits going through each column
+    demand: HashMap<(i32, i32), u32>,
+    /// Number of bits over the capacity of the columns
+    overflow: u32,
+}
+
+impl<'a> WireCost<'a> {
+    fn new(ports: &[Ports], layout: &'a LayoutConfig) -> Self {
+        let mut cost = Self {
+            layout,
+            ports: ports.to_vec(),
+            length: 0,
+            demand: HashMap::new(),
+            overflow: 0,
+        };
+        for &wire in ports {
+            cost.count(wire, true);
+        }
+        cost
+    }
+
+    fn total(&self) -> f64 {
+        self.length as f64 + self.overflow as f64 * CONGESTION_COST
+    }
+
+    /// Move the wire of the connection to the ports, returning the ones it had
+    fn replace(&mut self, connection: usize, ports: Ports) -> Ports {
+        let previous = mem::replace(&mut self.ports[connection], ports);
+        self.count(previous, false);
+        self.count(ports, true);
+        previous
+    }
+
+    /// Add or remove the wire from the length and the columns it goes through
+    fn count(&mut self, (from, to, bits): Ports, add: bool) {
+        let bin = |pos: Vector3| {
+            (
+                pos.0.div_euclid(self.layout.spacing),
+                pos.2.div_euclid(self.layout.spacing),
+            )
+        };
+
+        let length = from.distance(&to) * bits;
+        if add {
+            self.length += length;
+        } else {
+            self.length -= length;
+        }
+
+        let ((from_x, from_z), (to_x, to_z)) = (bin(from), bin(to));
+        for x in from_x.min(to_x)..=from_x.max(to_x) {
+            for z in from_z.min(to_z)..=from_z.max(to_z) {
+                let demand = self.demand.entry((x, z)).or_default();
+                self.overflow -= demand.saturating_sub(BIN_CAPACITY);
+                if add {
+                    *demand += bits;
+                } else {
+                    *demand -= bits;
+                }
+                self.overflow += demand.saturating_sub(BIN_CAPACITY);
+            }
+        }
+    }
+}
+
+/// Simulated annealing of the order and the orientations of the structures. Each step either swaps two structures
+/// or turns one, and keeps the move when it makes the wires cheaper, or with a probability decreasing with the
+/// temperature otherwise, to get out of the local minimums. Only the wires of the structures moved by a step are
+/// counted again. The random numbers are always the same, so is the layout of the same program
+fn anneal(
+    mut placement: Placement,
+    sizes: &[Vector3],
+    connections: &[Connection],
+    layout: &LayoutConfig,
+) -> Placement {
+    let nodes = placement.order.len();
+    if nodes == 0 {
+        return placement;
+    }
+
+    // The connections of each structure
+    let mut wires: Vec<Vec<usize>> = vec![Vec::new(); nodes];
+    for (i, connection) in connections.iter().enumerate() {
+        wires[connection.from].push(i);
+        if connection.to != connection.from {
+            wires[connection.to].push(i);
+        }
+    }
+
+    let mut random = Random(0x2545_F491_4F6C_DD1D);
+    let mut positions = placement.positions(sizes, layout);
+    let ports: Vec<Ports> = connections
+        .iter()
+        .map(|connection| connection.ports(&positions, &placement.orientations, sizes))
+        .collect();
+    let mut cost = WireCost::new(&ports, layout);
+    let mut current = cost.total();
+    let mut best = (current, placement.clone());
+    let mut moved_wires = vec![false; connections.len()];
+
+    let steps = ANNEALING_STEPS_PER_NODE * nodes;
+    let mut temperature = (current / nodes as f64).max(1.0);
+    let cooling = FINAL_TEMPERATURE.powf(1.0 / steps as f64);
+
+    for _ in 0..steps {
+        let (mut swapped, mut turned) = (None, None);
+        if nodes > 1 && random.below(2) == 0 {
+            let (a, b) = (random.below(nodes), random.below(nodes));
+            placement.order.swap(a, b);
+            swapped = Some((a, b));
+        } else {
+            let orientation = Orientation::ALL[random.below(Orientation::ALL.len())];
+            let node = random.below(nodes);
+            turned = Some((node, placement.orientations[node]));
+            placement.orientations[node] = orientation;
+        }
+
+        // The structures after a swapped one in its row move when their sizes differ
+        let next_positions = placement.positions(sizes, layout);
+        let mut moves = Vec::new();
+        for node in 0..nodes {
+            if next_positions[node] == positions[node] && turned.map(|(n, _)| n) != Some(node) {
+                continue;
+            }
+            for &wire in &wires[node] {
+                if !moved_wires[wire] {
+                    moved_wires[wire] = true;
+                    let ports =
+                        connections[wire].ports(&next_positions, &placement.orientations, sizes);
+                    moves.push((wire, cost.replace(wire, ports)));
+                }
+            }
+        }
+        for &(wire, _) in &moves {
+            moved_wires[wire] = false;
+        }
+
+        let next = cost.total();
+        if next <= current || random.unit() < ((current - next) / temperature).exp() {
+            current = next;
+            positions = next_positions;
+            if current < best.0 {
+                best = (current, placement.clone());
+            }
+        } else {
+            for (wire, ports) in moves.into_iter().rev() {
+                cost.replace(wire, ports);
+            }
+            if let Some((a, b)) = swapped {
+                placement.order.swap(a, b);
+            }
+            if let Some((node, orientation)) = turned {
+                placement.orientations[node] = orientation;
+            }
+        }
+
+        temperature *= cooling;
+    }
+
+    best.1
+}
+
+/// Sugiyama layered placement. The structures go in layers along x, each one after the structures its inputs come
+/// from, the edges closing a loop being reversed. The layers are then ordered along z to uncross the wires, and
+/// each structure is turned to bring its ports closer to the ones it is connected to
+fn layered(
+    sizes: &[Vector3],
+    controls: &[bool],
+    connections: &[Connection],
+    layout: &LayoutConfig,
+) -> Vec<(Vector3, Orientation)> {
+    let nodes = sizes.len();
+    let links: Vec<(usize, usize)> = connections.iter().map(|c| (c.from, c.to)).collect();
+    let links = acyclic(nodes, &links);
+
+    // Longest path from the structures without inputs. The links are acyclic, so each pass over them moves at
+    // least one more structure to its final layer
+    let mut layer_of = vec![0; nodes];
+    for _ in 0..nodes {
+        for &(from, to) in &links {
+            layer_of[to] = layer_of[to].max(layer_of[from] + 1);
+        }
+    }
+
+    // The links going over several layers go through dummy nodes in the layers between, so the ordering keeps
+    // their wires straight too
+    let mut layers = vec![Vec::new(); layer_of.iter().max().map_or(0, |&last| last + 1)];
+    for node in 0..nodes {
+        layers[layer_of[node]].push(node);
+    }
+    let mut adjacent = vec![(Vec::new(), Vec::new()); nodes];
+    for &(from, to) in &links {
+        let mut previous = from;
+        for layer in &mut layers[layer_of[from] + 1..layer_of[to]] {
+            let dummy = adjacent.len();
+            layer.push(dummy);
+            adjacent.push((vec![previous], Vec::new()));
+            adjacent[previous].1.push(dummy);
+            previous = dummy;
+        }
+        adjacent[previous].1.push(to);
+        adjacent[to].0.push(previous);
+    }
+
+    // Barycenter ordering, going down then up the layers: each node goes to the average rank of the nodes it is
+    // linked to in the layer it was ordered from. The control nodes stay first
+    let is_control = |node: usize| node < nodes && controls[node];
+    for sweep in 0..ORDERING_SWEEPS {
+        let mut rank = vec![0.0; adjacent.len()];
+        let down = sweep % 2 == 0;
+        let range: Vec<usize> = if down {
+            (0..layers.len()).collect()
+        } else {
+            (0..layers.len()).rev().collect()
+        };
+
+        for l in range {
+            let barycenters: HashMap<usize, f64> = layers[l]
+                .iter()
+                .enumerate()
+                .map(|(i, &node)| {
+                    let linked = if down {
+                        &adjacent[node].0
+                    } else {
+                        &adjacent[node].1
+                    };
+                    let barycenter = if linked.is_empty() {
+                        i as f64
+                    } else {
+                        linked.iter().map(|&other| rank[other]).sum::<f64>() / linked.len() as f64
+                    };
+                    (node, barycenter)
+                })
+                .collect();
+            layers[l].sort_by(|a, b| {
+                is_control(*b)
+                    .cmp(&is_control(*a))
+                    .then(barycenters[a].total_cmp(&barycenters[b]))
+            });
+            for (i, &node) in layers[l].iter().enumerate() {
+                rank[node] = i as f64;
+            }
+        }
+    }
+    for layer in &mut layers {
+        layer.retain(|&node| node < nodes);
+    }
+
+    // Turn each structure in the orientation with the cheapest wires, the others staying as they are
+    let mut orientations = vec![Orientation::default(); nodes];
+    let cost_of = |orientations: &[Orientation]| {
+        let positions = columns(&layers, &turned(sizes, orientations), controls, layout);
+        placed_cost(&positions, orientations, sizes, connections, layout)
+    };
+    for node in 0..nodes {
+        let mut best = (f64::INFINITY, Orientation::default());
+        for orientation in Orientation::ALL {
+            orientations[node] = orientation;
+            let cost = cost_of(&orientations);
+            if cost < best.0 {
+                best = (cost, orientation);
+            }
+        }
+        orientations[node] = best.1;
+    }
+
+    columns(&layers, &turned(sizes, &orientations), controls, layout)
+        .into_iter()
+        .zip(orientations)
+        .collect()
+}
+
+/// The links without the ones closing a loop, reversed: a depth first search keeps the links going forward, and
+/// the ones going back to a node still being searched are turned around
+fn acyclic(nodes: usize, links: &[(usize, usize)]) -> Vec<(usize, usize)> {
+    // 0 not searched yet, 1 being searched, 2 searched
+    let mut state = vec![0; nodes];
+    let mut acyclic = Vec::new();
+
+    for root in 0..nodes {
+        if state[root] != 0 {
+            continue;
+        }
+        state[root] = 1;
+        let mut stack = vec![(root, 0)];
+        while let Some((node, next)) = stack.pop() {
+            let outgoing = links.iter().filter(|&&(from, _)| from == node);
+            match outgoing.clone().nth(next) {
+                Some(&(_, to)) => {
+                    stack.push((node, next + 1));
+                    match state[to] {
+                        0 => {
+                            acyclic.push((node, to));
+                            state[to] = 1;
+                            stack.push((to, 0));
+                        }
+                        1 => acyclic.push((to, node)),
+                        _ => acyclic.push((node, to)),
+                    }
+                }
+                None => state[node] = 2,
+            }
+        }
+    }
+
+    acyclic.retain(|&(from, to)| from != to);
+    acyclic
+}
+
+/// Place the layers side by side along x, each one as wide as its widest structure. In each layer the control
+/// nodes come first, in a lane as deep as the control nodes of the fullest layer, then the others after it, all
+/// along z
+fn columns(
+    layers: &[Vec<usize>],
+    sizes: &[Vector3],
+    controls: &[bool],
+    layout: &LayoutConfig,
+) -> Vec<Vector3> {
+    let Vector3(origin_x, origin_y, origin_z) = layout.origin;
+    let control_lane = layers
+        .iter()
+        .map(|layer| {
+            layer
+                .iter()
+                .filter(|&&node| controls[node])
+                .map(|&node| sizes[node].2 + layout.spacing)
+                .sum()
+        })
+        .max()
+        .unwrap_or(0);
+
+    let mut positions = vec![layout.origin; sizes.len()];
+    let mut x = origin_x;
+    for layer in layers {
+        let (mut control_z, mut data_z) = (origin_z, origin_z + control_lane);
+        for &node in layer {
+            let z = if controls[node] {
+                &mut control_z
+            } else {
+                &mut data_z
+            };
+            positions[node] = Vector3(x, origin_y, *z);
+            *z += sizes[node].2 + layout.spacing;
+        }
+
+        x += layer.iter().map(|&node| sizes[node].0).max().unwrap_or(0) + layout.spacing;
+    }
+
+    positions
+}
+
+/// Xorshift random number generator
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number from 0 to `n` excluded
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    /// A number from 0 to 1
+    fn unit(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Structures of the size of a register, with wires from the first bit of the output of one to the first bit of
+    /// the input of another
+    fn structures(links: &[(usize, usize)], nodes: usize) -> (Vec<Vector3>, Vec<Connection>) {
+        let sizes = vec![Vector3(4, 16, 13); nodes];
+        let connections = links
+            .iter()
+            .map(|&(from, to)| Connection {
+                from,
+                to,
+                from_port: Vector3(3, 0, 12),
+                to_port: Vector3(3, 0, 4),
+                bits: 8,
+            })
+            .collect();
+        (sizes, connections)
+    }
+
+    fn layout(placement: PlacementKind) -> LayoutConfig {
+        LayoutConfig {
+            placement,
+            grid_width: 30,
+            ..LayoutConfig::default()
+        }
+    }
+
+    fn placed_cost_of(
+        placed: &[(Vector3, Orientation)],
+        sizes: &[Vector3],
+        connections: &[Connection],
+        layout: &LayoutConfig,
+    ) -> f64 {
+        let (positions, orientations): (Vec<Vector3>, Vec<Orientation>) =
+            placed.iter().copied().unzip();
+        placed_cost(&positions, &orientations, sizes, connections, layout)
+    }
+
+    /// Whether two of the structures take the same blocks
+    fn overlap(placed: &[(Vector3, Orientation)], sizes: &[Vector3]) -> bool {
+        let boxes: Vec<(Vector3, Vector3)> = placed
+            .iter()
+            .zip(sizes)
+            .map(|(&(pos, orientation), &size)| (pos, pos + orientation.size(size)))
+            .collect();
+        boxes.iter().enumerate().any(|(i, a)| {
+            boxes[i + 1..].iter().any(|b| {
+                a.0 .0 < b.1 .0
+                    && b.0 .0 < a.1 .0
+                    && a.0 .1 < b.1 .1
+                    && b.0 .1 < a.1 .1
+                    && a.0 .2 < b.1 .2
+                    && b.0 .2 < a.1 .2
+            })
+        })
+    }
+
+    #[test]
+    fn annealing_shortens_the_wires() {
+        // The structures linked together are compiled far from each other
+        let (sizes, connections) = structures(&[(0, 5), (5, 1), (1, 4), (4, 2), (2, 3)], 6);
+
+        let rows = layout(PlacementKind::Rows);
+        let annealing = layout(PlacementKind::Annealing);
+        let in_rows = place(&sizes, &[false; 6], &connections, &rows);
+        let annealed = place(&sizes, &[false; 6], &connections, &annealing);
+
+        assert!(!overlap(&annealed, &sizes));
+        assert!(
+            placed_cost_of(&annealed, &sizes, &connections, &annealing)
+                < placed_cost_of(&in_rows, &sizes, &connections, &rows)
+        );
+        // The same program is always placed the same way
+        assert_eq!(
+            annealed,
+            place(&sizes, &[false; 6], &connections, &annealing)
+        );
+    }
+
+    #[test]
+    fn moved_wires_cost_the_same_as_placed_ones() {
+        let layout = layout(PlacementKind::Annealing);
+        let wire = |from: (i32, i32), to: (i32, i32), bits| {
+            (Vector3(from.0, 0, from.1), Vector3(to.0, 0, to.1), bits)
+        };
+        let mut ports = vec![
+            wire((0, 0), (45, 5), 8),
+            wire((3, 12), (8, 40), 8),
+            wire((12, 3), (30, 30), 1),
+        ];
+        let mut cost = WireCost::new(&ports, &layout);
+
+        for (i, moved) in [
+            (1, wire((5, 5), (45, 8), 8)),
+            (0, wire((-12, 30), (7, 2), 8)),
+            (1, wire((3, 12), (8, 40), 8)),
+        ] {
+            assert_eq!(cost.replace(i, moved), ports[i]);
+            ports[i] = moved;
+            assert_eq!(cost.total(), WireCost::new(&ports, &layout).total());
+        }
+        // Three 8 bit wires through the same 4 columns, 8 bits over their capacity
+        assert_eq!(WireCost::new(&[ports[1]; 3], &layout).overflow, 8 * 4);
+    }
+
+    #[test]
+    fn turned_structures_keep_their_blocks_in_their_box() {
+        let size = Vector3(4, 16, 13);
+        let corners: Vec<Vector3> = [0, 3]
+            .into_iter()
+            .flat_map(|x| {
+                [0, 15]
+                    .into_iter()
+                    .flat_map(move |y| [0, 12].map(|z| Vector3(x, y, z)))
+            })
+            .collect();
+
+        for orientation in Orientation::ALL {
+            let turned = orientation.size(size);
+            let mut moved: Vec<Vector3> = corners
+                .iter()
+                .map(|&corner| orientation.apply(corner, size))
+                .collect();
+            for pos in &moved {
+                assert!((0..turned.0).contains(&pos.0), "{orientation:?} {pos:?}");
+                assert!((0..turned.1).contains(&pos.1), "{orientation:?} {pos:?}");
+                assert!((0..turned.2).contains(&pos.2), "{orientation:?} {pos:?}");
+            }
+            moved.sort_by_key(|pos| (pos.0, pos.1, pos.2));
+            moved.dedup();
+            assert_eq!(moved.len(), corners.len());
+        }
+
+        let turned = Orientation {
+            rotation: Rotation::Clockwise90,
+            mirror: false,
+        };
+        assert_eq!(turned.size(size), Vector3(13, 16, 4));
+        assert_eq!(
+            turned.place_arguments(Vector3(0, 0, 0), size),
+            "12 0 0 clockwise_90 none"
+        );
+    }
+
+    #[test]
+    fn facings_turn_with_the_structure() {
+        let turned = |rotation, mirror| Orientation { rotation, mirror };
+
+        assert_eq!(turned(Rotation::None, false).facing("north"), "north");
+        assert_eq!(turned(Rotation::Clockwise90, false).facing("north"), "east");
+        assert_eq!(turned(Rotation::Clockwise180, false).facing("east"), "west");
+        assert_eq!(
+            turned(Rotation::Counterclockwise90, false).facing("north"),
+            "west"
+        );
+        // Mirrored along z first
+        assert_eq!(turned(Rotation::None, true).facing("north"), "south");
+        assert_eq!(turned(Rotation::None, true).facing("east"), "east");
+        assert_eq!(turned(Rotation::Clockwise90, true).facing("north"), "west");
+    }
+
+    #[test]
+    fn layers_follow_the_data() {
+        // 0 and 1 feed 2, which feeds 3. Node 4 is a control node of the first layer
+        let links = [(0, 2), (1, 2), (2, 3)];
+        let (sizes, connections) = structures(&links, 5);
+        let controls = [false, false, false, false, true];
+        let layered = layout(PlacementKind::Layered);
+        let placed = place(&sizes, &controls, &connections, &layered);
+
+        assert!(!overlap(&placed, &sizes));
+        let x = |node: usize| placed[node].0 .0;
+        assert_eq!(x(0), x(1));
+        assert!(x(1) < x(2) && x(2) < x(3));
+        // The control node comes first in its layer
+        assert_eq!(x(4), x(0));
+        assert!(placed[4].0 .2 < placed[0].0 .2 && placed[4].0 .2 < placed[1].0 .2);
+    }
+
+    #[test]
+    fn loops_are_turned_around() {
+        let links = acyclic(3, &[(0, 1), (1, 2), (2, 0), (1, 1)]);
+        assert_eq!(links, vec![(0, 1), (1, 2), (0, 2)]);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::vectors::Vector3;
+use serde::{Deserialize, Serialize};
+
+/// Offsets from a wire block to the blocks another wire cannot use without coupling to it, following the redstone
+/// connectivity rules.
+/// A wire block is a green wool block with redstone dust or a repeater on top. For two wire blocks `d` apart:
+/// - they cannot overlap: the wool of one cannot be on the redstone of the other (`d = (0, ±1, 0)`)
+/// - dust connects to the dust and blocks on its sides (`d = (±1, 0, 0)`, `(0, 0, ±1)`)
+/// - dust connects to the dust one block up or down on its sides (`d = (±1, ±1, 0)`, `(0, ±1, ±1)`), which also
+///   covers the blocks cutting these connections on the slopes of a wire
+/// - a repeater only connects to its back and its front, its sides are isolated
+///
+/// Diagonal blocks (`d = (±1, _, ±1)`) never connect, and a wire can run two blocks above another one: its wool
+/// covers the redstone below without connecting to it.
+const COUPLED_OFFSETS: [Vector3; 15] = [
+    Vector3(0, 0, 0),
+    Vector3(0, 1, 0),
+    Vector3(0, -1, 0),
+    Vector3(1, 0, 0),
+    Vector3(-1, 0, 0),
+    Vector3(0, 0, 1),
+    Vector3(0, 0, -1),
+    Vector3(1, 1, 0),
+    Vector3(1, -1, 0),
+    Vector3(-1, 1, 0),
+    Vector3(-1, -1, 0),
+    Vector3(0, 1, 1),
+    Vector3(0, -1, 1),
+    Vector3(0, 1, -1),
+    Vector3(0, -1, -1),
+];
+
+/// The blocks another wire cannot use without coupling to the wire block. `repeater_direction` is the direction
+/// of the signal through the block when it holds a repeater
+pub fn coupled_blocks(
+    pos: Vector3,
+    repeater_direction: Option<Vector3>,
+) -> impl Iterator<Item = Vector3> {
+    COUPLED_OFFSETS
+        .into_iter()
+        .filter(move |offset| match repeater_direction {
+            // The sides of a repeater are isolated
+            Some(direction) => {
+                offset.0 * direction.0 + offset.2 * direction.2 != 0
+                    || (offset.0, offset.2) == (0, 0)
+            }
+            None => true,
+        })
+        .map(move |offset| pos + offset)
+}
+
+/// Whether two blocks of wires `offset` apart would couple, when both hold redstone dust
+pub fn is_coupled(offset: Vector3) -> bool {
+    COUPLED_OFFSETS.contains(&offset)
+}
+
+/// Height a torch tower climbs for each level. A torch inverts the signal, so a level is made of two of them
+pub const TOWER_STEP: i32 = 4;
+/// Highest torch tower a wire can climb at once
+pub const MAX_TOWER_LEVELS: i32 = 3;
+/// Longest delay a repeater can be set to, in ticks
+pub const MAX_REPEATER_DELAY: u32 = 4;
+/// Number of different blocks of a wire, to index them
+pub const WIRE_BLOCK_KINDS: usize = 2 + MAX_TOWER_LEVELS as usize;
+
+/// What a block of a wire is made of
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WireBlock {
+    /// Green wool with redstone dust on top
+    Redstone,
+    /// Green wool with a repeater on top, giving the full strength back after its delay in ticks
+    Repeater(u32),
+    /// A column of wool blocks with redstone torches in between, climbing `TOWER_STEP` blocks per level. The
+    /// redstone of the block before it powers the lowest wool block, and the highest one powers the redstone of the
+    /// block after it, one step further and higher. The wire keeps its full strength after the tower
+    Tower(i32),
+}
+
+impl WireBlock {
+    /// Index of the kind of block, from 0 to `WIRE_BLOCK_KINDS`
+    pub fn index(self) -> usize {
+        match self {
+            WireBlock::Redstone => 0,
+            WireBlock::Repeater(_) => 1,
+            WireBlock::Tower(levels) => 1 + levels as usize,
+        }
+    }
+
+    /// How much higher the wire continues after the block
+    pub fn height(self) -> i32 {
+        match self {
+            WireBlock::Tower(levels) => levels * TOWER_STEP,
+            _ => 0,
+        }
+    }
+
+    /// The positions of wire blocks the block takes: a wire block is the wool and what is on top of it
+    pub fn cells(self, pos: Vector3) -> impl Iterator<Item = Vector3> {
+        (0..=self.height()).map(move |y| pos + Vector3(0, y, 0))
+    }
+
+    /// The blocks another wire cannot use without coupling to the block. `direction` is the direction of the signal
+    /// through the block
+    pub fn coupled_blocks(self, pos: Vector3, direction: Option<Vector3>) -> Vec<Vector3> {
+        match self {
+            WireBlock::Redstone => coupled_blocks(pos, None).collect(),
+            WireBlock::Repeater(_) => coupled_blocks(pos, direction).collect(),
+            // The torches and the powered wool blocks reach all the blocks next to them, so another wire keeps away
+            // from the whole column, both with its wool and with the redstone on top of it
+            WireBlock::Tower(_) => {
+                let height = self.height();
+                let column = (-1..=height + 2).map(|y| Vector3(0, y, 0));
+                let sides = [(1, 0), (-1, 0), (0, 1), (0, -1)]
+                    .into_iter()
+                    .flat_map(|(x, z)| (0..=height + 1).map(move |y| Vector3(x, y, z)));
+                column.chain(sides).map(|offset| pos + offset).collect()
+            }
+        }
+    }
+
+    /// Ticks the signal takes to go through the block. Each torch of a tower takes one
+    pub fn delay(self) -> u32 {
+        match self {
+            WireBlock::Redstone => 0,
+            WireBlock::Repeater(delay) => delay,
+            WireBlock::Tower(levels) => 2 * levels as u32,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wires_couple_to_the_blocks_they_connect_to() {
+        // On the sides, and one block up or down on the sides
+        assert!(is_coupled(Vector3(1, 0, 0)));
+        assert!(is_coupled(Vector3(0, -1, 1)));
+        // Diagonal blocks never connect, and a wire can run two blocks above another one
+        assert!(!is_coupled(Vector3(1, 0, 1)));
+        assert!(!is_coupled(Vector3(1, 1, 1)));
+        assert!(!is_coupled(Vector3(0, 2, 0)));
+        assert!(!is_coupled(Vector3(2, 0, 0)));
+
+        // A repeater only connects to its back and its front
+        let pos = Vector3(5, 5, 5);
+        let repeater = WireBlock::Repeater(1).coupled_blocks(pos, Some(Vector3(1, 0, 0)));
+        for offset in [Vector3(1, 0, 0), Vector3(-1, 1, 0), Vector3(0, 1, 0)] {
+            assert!(repeater.contains(&(pos + offset)));
+        }
+        for offset in [Vector3(0, 0, 1), Vector3(0, -1, -1)] {
+            assert!(!repeater.contains(&(pos + offset)));
+        }
+        let redstone = WireBlock::Redstone.coupled_blocks(pos, Some(Vector3(1, 0, 0)));
+        assert_eq!(redstone.len(), COUPLED_OFFSETS.len());
+    }
+
+    #[test]
+    fn towers_take_their_column_and_two_ticks_per_level() {
+        let (pos, tower) = (Vector3(0, 0, 0), WireBlock::Tower(2));
+        assert_eq!(tower.height(), 2 * TOWER_STEP);
+        assert_eq!(tower.delay(), 4);
+        let cells: Vec<Vector3> = tower.cells(pos).collect();
+        assert_eq!(cells.len(), 2 * TOWER_STEP as usize + 1);
+        assert_eq!(cells.last(), Some(&Vector3(0, 2 * TOWER_STEP, 0)));
+
+        // The column from below the lowest wool to above the redstone on top, and the sides up to that redstone
+        let coupled = tower.coupled_blocks(pos, Some(Vector3(1, 0, 0)));
+        let top = 2 * TOWER_STEP + 1;
+        for offset in [
+            Vector3(0, -1, 0),
+            Vector3(0, top + 1, 0),
+            Vector3(0, 0, 1),
+            Vector3(-1, top, 0),
+        ] {
+            assert!(coupled.contains(&(pos + offset)));
+        }
+        for offset in [
+            Vector3(0, top + 2, 0),
+            Vector3(1, top + 1, 0),
+            Vector3(1, 0, 1),
+        ] {
+            assert!(!coupled.contains(&(pos + offset)));
+        }
+
+        // Each height of tower is a kind of block of its own
+        let kinds: Vec<usize> = (1..=MAX_TOWER_LEVELS)
+            .map(|levels| WireBlock::Tower(levels).index())
+            .collect();
+        assert_eq!(kinds, (2..WIRE_BLOCK_KINDS).collect::<Vec<_>>());
+    }
+}
@@ -0,0 +1,634 @@
+use crate::{
+    grid::RoutingGrid,
+    pathfinding::{PathNode, Wire},
+    redstone::{self, WireBlock, WIRE_BLOCK_KINDS},
+    vectors::{Vector3, REPEATER_STRENGTH},
+};
+use std::{
+    cmp::Reverse,
+    collections::{BinaryHeap, HashMap, HashSet},
+    str::FromStr,
+};
+
+/// How much cheaper the cells of the ribbon a wire follows are
+const RIBBON_DISCOUNT: u32 = 4;
+/// Longest straight run the straight runs search goes over at once
+const MAX_RUN: usize = 32;
+/// Distance to the ports under which the straight runs search expands every block, the moves allowed changing there
+const PORT_AREA: u32 = 9;
+/// Number of horizontal directions a block is reached from
+const DIRECTIONS: usize = 4;
+/// Number of search states of a cell: the redstone, and the other blocks reached from each direction
+const CELL_STATES: usize = 1 + (WIRE_BLOCK_KINDS - 1) * DIRECTIONS;
+/// Number of blocks before a new one the search checks it does not couple to. Coupling to an older block takes a
+/// loop back, the whole wire is checked for these when it reaches the goal
+const SELF_COUPLING_WINDOW: usize = 16;
+
+/// A wire to search: where it starts and goes, the ribbon it follows and the tree it branches off
+pub struct Route {
+    /// The output port the signal comes from
+    pub start: Vector3,
+    pub goal: Vector3,
+    /// The blocks the wire can start from, with their signal: the port, or the blocks of the wires already routed
+    /// from it
+    pub sources: Vec<PathNode>,
+    /// The cells of the wire of the previous bit of the bus, cheaper to follow
+    pub ribbon: HashSet<Vector3>,
+    /// Number of blocks of the wires already routed from the port each cell would couple to. Only the first block
+    /// of the wire couples to them, to the block it branches off
+    pub tree: HashMap<Vector3, u32>,
+}
+
+impl Route {
+    /// Whether the block would couple to the wires already routed from the port, other than the block `source` the
+    /// wire branches off
+    pub fn is_tree_coupled(&self, block: WireBlock, pos: Vector3, source: Vector3) -> bool {
+        block.cells(pos).any(|cell| {
+            let couplings = self.tree.get(&cell).copied().unwrap_or(0);
+            couplings > redstone::is_coupled(cell - source) as u32
+        })
+    }
+}
+
+/// Memory of the searches, kept from one to the next so it is not allocated for each net
+#[derive(Default)]
+pub struct SearchBuffers {
+    /// Strongest signal already expanded in each state
+    strongest: Vec<u8>,
+    /// The states set by the last search, the only ones to clear for the next one
+    touched: Vec<usize>,
+}
+
+impl SearchBuffers {
+    /// Clear the states of the last search, with room for all the states of the grid
+    fn reset(&mut self, grid: &RoutingGrid) {
+        for i in self.touched.drain(..) {
+            self.strongest[i] = 0;
+        }
+        let states = grid.cells() * CELL_STATES;
+        if self.strongest.len() != states {
+            self.strongest = vec![0; states];
+        }
+    }
+
+    fn strongest(&self, state: usize) -> u8 {
+        self.strongest[state]
+    }
+
+    fn set_strongest(&mut self, state: usize, strength: u8) {
+        self.strongest[state] = strength;
+        self.touched.push(state);
+    }
+}
+
+/// A search algorithm finding the wire of a net in the routing grid
+pub trait Router: Sync {
+    /// Name of the algorithm, for the reports
+    fn name(&self) -> &'static str;
+
+    /// Find a wire from one of the sources of the route to its goal, the cells of the ribbon being cheaper.
+    /// Without any path, returns the block closest to the goal the search reached
+    fn find_path(
+        &self,
+        route: &Route,
+        grid: &RoutingGrid,
+        buffers: &mut SearchBuffers,
+    ) -> Result<Wire, Vector3>;
+}
+
+/// The routers that can be selected in the configuration
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum RouterKind {
+    #[default]
+    AStar,
+    Lee,
+    StraightRuns,
+}
+
+impl RouterKind {
+    pub fn router(self) -> Box<dyn Router> {
+        match self {
+            RouterKind::AStar => Box::new(AStar),
+            RouterKind::Lee => Box::new(Lee),
+            RouterKind::StraightRuns => Box::new(StraightRuns),
+        }
+    }
+}
+
+impl FromStr for RouterKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "a-star" => Ok(RouterKind::AStar),
+            "lee" => Ok(RouterKind::Lee),
+            "straight-runs" => Ok(RouterKind::StraightRuns),
+            _ => Err(format!(
+                "Unknown router {s:?}, expected \"a-star\", \"lee\" or \"straight-runs\""
+            )),
+        }
+    }
+}
+
+/// A* search, expanding first the blocks with the lowest cost from the start plus the estimated cost to the goal
+pub struct AStar;
+
+impl Router for AStar {
+    fn name(&self) -> &'static str {
+        "A*"
+    }
+
+    fn find_path(
+        &self,
+        route: &Route,
+        grid: &RoutingGrid,
+        buffers: &mut SearchBuffers,
+    ) -> Result<Wire, Vector3> {
+        search(route, grid, true, buffers, |node| steps(route, grid, node))
+    }
+}
+
+/// Lee maze router: a wave expanding from the start in the order of the cost, without looking at where the goal is.
+/// It searches more blocks than A*, but the wire it finds is not always the cheapest: the redstone on a block is
+/// only searched further from the strongest signal reaching it, which may not be able to turn or climb where a
+/// weaker one from another direction could, and the paths are not told apart by the blocks they would couple to
+pub struct Lee;
+
+impl Router for Lee {
+    fn name(&self) -> &'static str {
+        "Lee"
+    }
+
+    fn find_path(
+        &self,
+        route: &Route,
+        grid: &RoutingGrid,
+        buffers: &mut SearchBuffers,
+    ) -> Result<Wire, Vector3> {
+        search(route, grid, false, buffers, |node| steps(route, grid, node))
+    }
+}
+
+/// A* that goes over the straight runs at once, only stopping where the wire could do something else than going
+/// straight on: next to a change of the obstacles on its sides, in line with the goal, near the ports, near other
+/// wires or when the signal gets weak enough for a repeater. Unlike a jump point search, it does not prune the
+/// blocks on the sides of a run, they are all expanded from the blocks it stops at
+pub struct StraightRuns;
+
+impl Router for StraightRuns {
+    fn name(&self) -> &'static str {
+        "straight runs"
+    }
+
+    fn find_path(
+        &self,
+        route: &Route,
+        grid: &RoutingGrid,
+        buffers: &mut SearchBuffers,
+    ) -> Result<Wire, Vector3> {
+        search(route, grid, true, buffers, |node| {
+            let mut runs = steps(route, grid, node);
+            for chain in &mut runs {
+                extend_run(route, grid, node, chain);
+            }
+            runs
+        })
+    }
+}
+
+/// The moves from the node, each one a single block with its cost
+fn steps(route: &Route, grid: &RoutingGrid, node: &PathNode) -> Vec<Vec<(PathNode, u32)>> {
+    node.position
+        .neighbors(&route.start, &route.goal, grid, node)
+        .into_iter()
+        .map(|step| vec![step])
+        .collect()
+}
+
+/// Extend the move going straight on from the node with the next blocks, up to the next block the wire could turn
+/// at or the blocks coupling to the wires of the same port
+fn extend_run(
+    route: &Route,
+    grid: &RoutingGrid,
+    node: &PathNode,
+    chain: &mut Vec<(PathNode, u32)>,
+) {
+    let Route {
+        start,
+        goal,
+        ribbon,
+        tree,
+        ..
+    } = route;
+    let Some(previous) = node.previous else {
+        return;
+    };
+    let direction = node.position - previous;
+    let is_straight = |from: &PathNode, to: &PathNode| {
+        to.block == WireBlock::Redstone && to.position - from.position == direction
+    };
+    if direction.1 != 0 || node.block != WireBlock::Redstone || !is_straight(node, &chain[0].0) {
+        return;
+    }
+
+    // The blocks on the sides, and the slopes ahead
+    let around = [
+        Vector3(direction.2, 0, direction.0),
+        Vector3(-direction.2, 0, -direction.0),
+        direction + Vector3(0, 1, 0),
+        direction + Vector3(0, -1, 0),
+    ];
+
+    while chain.len() < MAX_RUN {
+        let tip = &chain[chain.len() - 1].0;
+        let pos = tip.position;
+        let behind = pos - direction;
+
+        let is_turn = pos.distance(start) < PORT_AREA
+            || pos.distance(goal) < PORT_AREA
+            || pos.0 == goal.0
+            || pos.2 == goal.2
+            || tip.strength <= REPEATER_STRENGTH + 1
+            || grid.usage(&pos) > 0
+            || ribbon.contains(&pos)
+            || around
+                .iter()
+                .any(|&o| grid.is_obstacle_nearby(pos + o) != grid.is_obstacle_nearby(behind + o));
+        if is_turn {
+            return;
+        }
+
+        let next = pos
+            .neighbors(start, goal, grid, tip)
+            .into_iter()
+            .find(|(next, _)| is_straight(tip, next));
+        match next {
+            Some(step) if !tree.contains_key(&step.0.position) => chain.push(step),
+            _ => return,
+        }
+    }
+}
+
+/// Best-first search shared by the routers. `expand` gives the moves from a node, each one a chain of blocks with
+/// their costs, and the search goes on from the last block of the chain. The blocks are expanded in the order of
+/// their cost from the sources, plus the estimated cost to the goal with `use_distance`: the lowest cost of the
+/// steps to it, through the ports area around it. All the sources start at 0, so a wire branches off where it is
+/// the cheapest. The blocks next to the ports are cheaper, so the search is not always the cheapest around them,
+/// but it keeps from expanding all the blocks as cheap as the ones in front of it.
+/// The signal strength is part of the search state, but a block is only searched again when the signal reaches it
+/// stronger than before in the same `state`: a weaker signal on the same block cannot go further
+fn search(
+    route: &Route,
+    grid: &RoutingGrid,
+    use_distance: bool,
+    buffers: &mut SearchBuffers,
+    expand: impl Fn(&PathNode) -> Vec<Vec<(PathNode, u32)>>,
+) -> Result<Wire, Vector3> {
+    let goal = route.goal;
+
+    // Each searched node, the index of the one before it and the index of the source it comes from
+    let mut nodes: Vec<(PathNode, Option<usize>, usize)> = Vec::new();
+    // Priority, cost from the start and index of the nodes to expand
+    let mut queue = BinaryHeap::new();
+    for source in &route.sources {
+        queue.push(Reverse((0u32, 0u32, nodes.len())));
+        nodes.push((source.clone(), None, nodes.len()));
+    }
+    buffers.reset(grid);
+    // The cells of the ribbon can be cheaper than the ports area
+    let discount = if route.ribbon.is_empty() {
+        1
+    } else {
+        RIBBON_DISCOUNT
+    };
+    let to_goal = grid.costs_to_port(goal, discount);
+    let key = |node: &PathNode| state(grid, node);
+
+    let mut nearest = route.start;
+
+    while let Some(Reverse((_, cost, i))) = queue.pop() {
+        let node = nodes[i].0.clone();
+        if buffers.strongest(key(&node)) >= node.strength {
+            continue;
+        }
+        buffers.set_strongest(key(&node), node.strength);
+
+        if node.position.distance(&goal) < nearest.distance(&goal) {
+            nearest = node.position;
+        }
+
+        if node.position == goal {
+            let mut wire = Wire {
+                path: Vec::new(),
+                blocks: Vec::new(),
+            };
+            let mut current = Some(i);
+            while let Some(i) = current {
+                wire.path.push(nodes[i].0.position);
+                wire.blocks.push(nodes[i].0.block);
+                current = nodes[i].1;
+            }
+            wire.path.reverse();
+            wire.blocks.reverse();
+            if is_wire_self_coupled(&wire) {
+                // The goal can still be reached another way
+                buffers.set_strongest(key(&node), 0);
+                continue;
+            }
+            return Ok(wire);
+        }
+
+        let source = nodes[i].2;
+        'moves: for chain in expand(&node) {
+            let Some((last, _)) = chain.last() else {
+                continue;
+            };
+            if buffers.strongest(key(last)) >= last.strength {
+                continue;
+            }
+            // The wire cannot couple to itself, it would latch its own signal. A chain goes straight on, so its
+            // blocks can only couple to the ones before it. It cannot couple to the other wires of the port either,
+            // but to the block it branches off
+            for (j, (next, _)) in chain.iter().enumerate() {
+                let ancestors = if j == 0 { nodes[i].1 } else { Some(i) };
+                if is_self_coupled(&nodes, ancestors, next)
+                    || route.is_tree_coupled(next.block, next.position, nodes[source].0.position)
+                {
+                    continue 'moves;
+                }
+            }
+
+            let mut next_cost = cost;
+            let mut parent = i;
+            for (next, step_cost) in chain {
+                let step_cost =
+                    if route.ribbon.contains(&next.position) && grid.usage(&next.position) == 0 {
+                        (step_cost / RIBBON_DISCOUNT).max(1)
+                    } else {
+                        step_cost
+                    };
+                next_cost = next_cost.saturating_add(step_cost);
+                nodes.push((next, Some(parent), source));
+                parent = nodes.len() - 1;
+            }
+
+            let distance = if use_distance {
+                to_goal.at(nodes[parent].0.position.distance(&goal))
+            } else {
+                0
+            };
+            queue.push(Reverse((
+                next_cost.saturating_add(distance),
+                next_cost,
+                parent,
+            )));
+        }
+    }
+
+    Err(nearest)
+}
+
+/// Index of the state of the node in the search, from 0 to `grid.cells() * CELL_STATES`. A repeater or a tower only
+/// passes the signal straight on, so it is searched again when reached from another direction. The redstone can go
+/// most ways whatever the direction, it is searched once
+fn state(grid: &RoutingGrid, node: &PathNode) -> usize {
+    let state = match (node.block, node.previous) {
+        (WireBlock::Redstone, _) | (_, None) => 0,
+        (block, Some(previous)) => {
+            let direction = match node.position - previous {
+                Vector3(1, _, _) => 0,
+                Vector3(-1, _, _) => 1,
+                Vector3(_, _, 1) => 2,
+                _ => 3,
+            };
+            1 + (block.index() - 1) * DIRECTIONS + direction
+        }
+    };
+    grid.index(node.position) * CELL_STATES + state
+}
+
+/// Whether the block would couple to one of the last `SELF_COUPLING_WINDOW` blocks from `current` back to the
+/// start of the searched path
+fn is_self_coupled(
+    nodes: &[(PathNode, Option<usize>, usize)],
+    mut current: Option<usize>,
+    next: &PathNode,
+) -> bool {
+    for _ in 0..SELF_COUPLING_WINDOW {
+        let Some(i) = current else {
+            break;
+        };
+        let node = &nodes[i].0;
+        for cell in next.block.cells(next.position) {
+            if node
+                .block
+                .cells(node.position)
+                .any(|other| redstone::is_coupled(cell - other))
+            {
+                return true;
+            }
+        }
+        current = nodes[i].1;
+    }
+    false
+}
+
+/// Whether a block of the wire couples to one of the blocks before the previous one, looping back to it
+fn is_wire_self_coupled(wire: &Wire) -> bool {
+    // Index of the first block taking each cell
+    let mut taken: HashMap<Vector3, usize> = HashMap::new();
+    for (j, (&pos, &block)) in wire.path.iter().zip(&wire.blocks).enumerate() {
+        if j >= 2 {
+            let coupled = block
+                .cells(pos)
+                .flat_map(|cell| redstone::coupled_blocks(cell, None))
+                .any(|cell| taken.get(&cell).is_some_and(|&k| k + 1 < j));
+            if coupled {
+                return true;
+            }
+        }
+        for cell in block.cells(pos) {
+            taken.entry(cell).or_insert(j);
+        }
+    }
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pathfinding::SOURCE_STRENGTH;
+
+    fn wire(path: &[(i32, i32, i32)]) -> Wire {
+        Wire {
+            path: path.iter().map(|&(x, y, z)| Vector3(x, y, z)).collect(),
+            blocks: vec![WireBlock::Redstone; path.len()],
+        }
+    }
+
+    fn route(start: Vector3, goal: Vector3) -> Route {
+        Route {
+            start,
+            goal,
+            sources: vec![PathNode {
+                position: start,
+                previous: None,
+                strength: SOURCE_STRENGTH,
+                block: WireBlock::Redstone,
+            }],
+            ribbon: HashSet::new(),
+            tree: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn routers_find_a_wire_around_a_wall() {
+        let (start, goal) = (Vector3(2, 2, 2), Vector3(18, 2, 18));
+        // A wall across the grid, with a gap on one side
+        let obstacles: HashSet<Vector3> = (0..16)
+            .flat_map(|x| (0..12).map(move |y| Vector3(x, y, 10)))
+            .collect();
+        let grid = RoutingGrid::new(
+            Vector3(0, 0, 0),
+            Vector3(20, 12, 20),
+            &obstacles,
+            &[start, goal],
+        );
+
+        for kind in [RouterKind::AStar, RouterKind::Lee, RouterKind::StraightRuns] {
+            let wire = kind
+                .router()
+                .find_path(&route(start, goal), &grid, &mut SearchBuffers::default())
+                .unwrap_or_else(|_| panic!("{kind:?} found no wire"));
+            assert_eq!(wire.path.first(), Some(&start));
+            assert_eq!(wire.path.last(), Some(&goal));
+            assert!(wire.path.iter().all(|pos| !obstacles.contains(pos)));
+            assert!(!is_wire_self_coupled(&wire));
+        }
+    }
+
+    #[test]
+    fn walled_in_goal_gives_the_nearest_block() {
+        let (start, goal) = (Vector3(2, 2, 2), Vector3(18, 2, 18));
+        let obstacles: HashSet<Vector3> = (0..=20)
+            .flat_map(|x| (0..=12).map(move |y| Vector3(x, y, 10)))
+            .collect();
+        let grid = RoutingGrid::new(
+            Vector3(0, 0, 0),
+            Vector3(20, 12, 20),
+            &obstacles,
+            &[start, goal],
+        );
+
+        let nearest = AStar
+            .find_path(&route(start, goal), &grid, &mut SearchBuffers::default())
+            .unwrap_err();
+        assert!(nearest.2 < 10);
+    }
+
+    #[test]
+    fn buffers_are_reused_between_searches() {
+        let grid = RoutingGrid::new(Vector3(0, 0, 0), Vector3(20, 12, 20), &HashSet::new(), &[]);
+        let mut buffers = SearchBuffers::default();
+        let first = AStar
+            .find_path(
+                &route(Vector3(2, 2, 2), Vector3(18, 2, 18)),
+                &grid,
+                &mut buffers,
+            )
+            .unwrap();
+        let allocated = buffers.strongest.as_ptr();
+
+        // The other way round, from a goal expanded by the first search
+        let second = AStar
+            .find_path(
+                &route(Vector3(18, 2, 18), Vector3(2, 2, 2)),
+                &grid,
+                &mut buffers,
+            )
+            .unwrap();
+        assert_eq!(second.path.len(), first.path.len());
+        assert_eq!(buffers.strongest.as_ptr(), allocated);
+        assert_eq!(buffers.strongest.len(), grid.cells() * CELL_STATES);
+
+        buffers.reset(&grid);
+        assert!(buffers.touched.is_empty());
+        assert!(buffers.strongest.iter().all(|&strength| strength == 0));
+    }
+
+    #[test]
+    fn search_through_the_ports_area_stays_near_the_wire() {
+        // The first bit of an 8 bit bus, the ports of the other bits being in the way
+        let bits = |x: i32| (0..8).map(move |z| Vector3(x, 10, 10 + 2 * z));
+        let entries: Vec<Vector3> = bits(10).chain(bits(34)).collect();
+        let grid = RoutingGrid::new(
+            Vector3(0, 0, 0),
+            Vector3(44, 40, 34),
+            &HashSet::new(),
+            &entries,
+        );
+
+        let mut buffers = SearchBuffers::default();
+        let wire = AStar
+            .find_path(&route(entries[0], entries[8]), &grid, &mut buffers)
+            .unwrap();
+        assert_eq!(wire.path.len(), 25);
+        // Without the cost of the ports area in the estimate, the search expanded every cell more than twice
+        assert!(buffers.touched.len() < grid.cells());
+    }
+
+    #[test]
+    fn blocks_reached_another_way_are_searched_again() {
+        let grid = RoutingGrid::new(Vector3(0, 0, 0), Vector3(9, 9, 9), &HashSet::new(), &[]);
+        let node = |previous: Option<(i32, i32, i32)>, block| PathNode {
+            position: Vector3(5, 5, 5),
+            previous: previous.map(|(x, y, z)| Vector3(x, y, z)),
+            strength: SOURCE_STRENGTH,
+            block,
+        };
+
+        let directions = [(4, 5, 5), (6, 5, 5), (5, 5, 4), (5, 5, 6)];
+        let mut states: Vec<usize> = [WireBlock::Repeater(1), WireBlock::Tower(3)]
+            .into_iter()
+            .flat_map(|block| directions.map(|previous| state(&grid, &node(Some(previous), block))))
+            .collect();
+        states.sort_unstable();
+        states.dedup();
+        assert_eq!(states.len(), 8);
+        assert!(states.iter().all(|&i| i < grid.cells() * CELL_STATES));
+
+        // The redstone can go on whichever way it came from
+        let redstone = state(&grid, &node(None, WireBlock::Redstone));
+        assert_eq!(
+            state(&grid, &node(Some((4, 4, 5)), WireBlock::Redstone)),
+            redstone
+        );
+        assert!(!states.contains(&redstone));
+    }
+
+    #[test]
+    fn wire_looping_back_couples_to_itself() {
+        assert!(!is_wire_self_coupled(&wire(&[
+            (0, 0, 0),
+            (1, 0, 0),
+            (2, 0, 0),
+            (2, 0, 1),
+            (2, 0, 2)
+        ])));
+        assert!(is_wire_self_coupled(&wire(&[
+            (0, 0, 0),
+            (1, 0, 0),
+            (1, 0, 1),
+            (0, 0, 1)
+        ])));
+        // Two blocks above the wire, the loop is isolated by the wool
+        assert!(!is_wire_self_coupled(&wire(&[
+            (0, 0, 0),
+            (1, 0, 0),
+            (2, 1, 0),
+            (2, 2, 1),
+            (1, 2, 1),
+            (0, 2, 1)
+        ])));
+    }
+}
@@ -0,0 +1,287 @@
+use crate::{
+    nodes::{Edge, Node, NodeType},
+    pathfinding::RoutedWire,
+};
+use std::collections::{HashMap, VecDeque};
+
+/// Static timing analysis of the placed nodes and the routed wires: the tick each signal changes at, once the nodes
+/// without inputs change at tick 0
+pub struct Timing {
+    /// Nodes in the order the signal goes through them
+    order: Vec<usize>,
+    /// Node each wire starts from, and the wires arriving at each node
+    from: Vec<usize>,
+    wires_to: Vec<Vec<usize>>,
+    /// Ticks each node takes from its inputs to its outputs
+    delays: Vec<u32>,
+    /// Tick the signal of each wire arrives at its node
+    arrivals: Vec<u32>,
+    /// Tick the outputs of each node change, after its latest input
+    outputs: Vec<u32>,
+}
+
+impl Timing {
+    pub fn new(nodes: &[Node], edges: &[Edge], wires: &[RoutedWire]) -> Self {
+        let index: HashMap<&str, usize> = nodes
+            .iter()
+            .enumerate()
+            .map(|(i, node)| (node.id.as_str(), i))
+            .collect();
+
+        let mut from = Vec::new();
+        let mut wires_to = vec![Vec::new(); nodes.len()];
+        for (w, routed) in wires.iter().enumerate() {
+            let edge = &edges[routed.edge];
+            from.push(index[edge.from_node.as_str()]);
+            wires_to[index[edge.to_node.as_str()]].push(w);
+        }
+
+        let mut timing = Self {
+            order: order(nodes.len(), edges, &index),
+            from,
+            wires_to,
+            delays: nodes
+                .iter()
+                .map(|node| NodeType::get_delay(node.node.clone()))
+                .collect(),
+            arrivals: vec![0; wires.len()],
+            outputs: vec![0; nodes.len()],
+        };
+        for i in 0..timing.order.len() {
+            let node = timing.order[i];
+            let latest = timing.arrive(node, wires);
+            timing.outputs[node] = latest + timing.delays[node];
+        }
+
+        timing
+    }
+
+    /// Compute when the wires of the node arrive, and return the latest one
+    fn arrive(&mut self, node: usize, wires: &[RoutedWire]) -> u32 {
+        for &w in &self.wires_to[node] {
+            self.arrivals[w] = self.outputs[self.from[w]] + wires[w].delay();
+        }
+        self.wires_to[node]
+            .iter()
+            .map(|&w| self.arrivals[w])
+            .max()
+            .unwrap_or(0)
+    }
+
+    /// Delay the wires arriving before the others at their node, so all the inputs of a node arrive at the same
+    /// tick. Returns the ticks of repeaters added, and the nodes that could not be balanced because their wires
+    /// are too short or too bent for the repeaters
+    pub fn balance(&mut self, wires: &mut [RoutedWire]) -> (u32, Vec<usize>) {
+        let mut added = 0;
+        let mut unbalanced = Vec::new();
+
+        for i in 0..self.order.len() {
+            let node = self.order[i];
+            let latest = self.arrive(node, wires);
+
+            for w in self.wires_to[node].clone() {
+                let ticks = latest - self.arrivals[w];
+                let missing = wires[w].add_delay(ticks);
+                added += ticks - missing;
+                self.arrivals[w] = latest - missing;
+
+                if missing > 0 && !unbalanced.contains(&node) {
+                    unbalanced.push(node);
+                }
+            }
+
+            self.outputs[node] = latest + self.delays[node];
+        }
+
+        (added, unbalanced)
+    }
+
+    /// The nodes whose inputs arrive at different ticks, with the first and the last tick
+    pub fn races(&self) -> Vec<(usize, u32, u32)> {
+        self.order
+            .iter()
+            .filter_map(|&node| {
+                let arrivals = self.wires_to[node].iter().map(|&w| self.arrivals[w]);
+                let first = arrivals.clone().min()?;
+                let last = arrivals.max()?;
+                (first != last).then_some((node, first, last))
+            })
+            .collect()
+    }
+
+    /// The slowest chain of nodes, from a node without inputs, and the tick its last node changes at
+    pub fn critical_path(&self) -> (Vec<usize>, u32) {
+        let Some(&last) = self.order.iter().max_by_key(|&&node| self.outputs[node]) else {
+            return (Vec::new(), 0);
+        };
+
+        let mut path = vec![last];
+        let mut node = last;
+        while let Some(&w) = self.wires_to[node]
+            .iter()
+            .max_by_key(|&&w| self.arrivals[w])
+        {
+            node = self.from[w];
+            // A loop has no first node, stop when coming back on it
+            if path.contains(&node) {
+                break;
+            }
+            path.push(node);
+        }
+        path.reverse();
+
+        (path, self.outputs[last])
+    }
+
+    pub fn print_report(&self, nodes: &[Node], edges: &[Edge], wires: &[RoutedWire]) {
+        let name = |node: usize| NodeType::get_name(nodes[node].node.clone());
+
+        for edge_i in 0..edges.len() {
+            let delays = wires
+                .iter()
+                .filter(|routed| routed.edge == edge_i)
+                .map(|routed| routed.delay());
+            let (Some(min), Some(max)) = (delays.clone().min(), delays.max()) else {
+                continue;
+            };
+
+            if min == max {
+                println!("[EDGE {}/{}] {max} tick(s)", edge_i + 1, edges.len());
+            } else {
+                println!(
+                    "[EDGE {}/{}] {min} to {max} tick(s) depending on the bit",
+                    edge_i + 1,
+                    edges.len()
+                );
+            }
+        }
+
+        let (path, ticks) = self.critical_path();
+        if !path.is_empty() {
+            let path: Vec<&str> = path.into_iter().map(name).collect();
+            println!(
+                "Critical path: {ticks} tick(s) through {}",
+                path.join(" -> ")
+            );
+        }
+
+        for (node, first, last) in self.races() {
+            println!(
+                "[RACE] The inputs of the {} node arrive from tick {first} to tick {last}",
+                name(node)
+            );
+        }
+    }
+}
+
+/// Order the nodes so each one comes after the nodes its inputs come from. The nodes in a loop come last, in the
+/// order they were compiled
+fn order(nodes: usize, edges: &[Edge], index: &HashMap<&str, usize>) -> Vec<usize> {
+    let mut inputs = vec![0; nodes];
+    let mut outputs = vec![Vec::new(); nodes];
+    for edge in edges {
+        let (from, to) = (index[edge.from_node.as_str()], index[edge.to_node.as_str()]);
+        inputs[to] += 1;
+        outputs[from].push(to);
+    }
+
+    let mut order = Vec::new();
+    let mut ready: VecDeque<usize> = (0..nodes).filter(|&n| inputs[n] == 0).collect();
+    while let Some(node) = ready.pop_front() {
+        order.push(node);
+        for &next in &outputs[node] {
+            inputs[next] -= 1;
+            if inputs[next] == 0 {
+                ready.push_back(next);
+            }
+        }
+    }
+
+    order.extend((0..nodes).filter(|&node| inputs[node] > 0));
+
+    order
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parser::ast::Operator, pathfinding::Wire, redstone::WireBlock, vectors::Vector3};
+
+    fn edge(from: &Node, to: &Node, input: usize) -> Edge {
+        Edge {
+            from_node: from.id.clone(),
+            from_port: from.outputs[0].id.clone(),
+            to_node: to.id.clone(),
+            to_port: to.inputs[input].id.clone(),
+            shift: 0,
+            mask: u32::MAX,
+        }
+    }
+
+    /// A straight flat wire of the edge, with a repeater of the delay after its first block
+    fn wire(edge: usize, length: i32, delay: u32) -> RoutedWire {
+        let mut blocks = vec![WireBlock::Redstone; length as usize];
+        if delay > 0 {
+            blocks[1] = WireBlock::Repeater(delay);
+        }
+        RoutedWire {
+            edge,
+            bit: 0,
+            wire: Wire {
+                path: (0..length).map(|x| Vector3(x, 0, 0)).collect(),
+                blocks,
+            },
+            source_delay: 0,
+            last_branch: 0,
+        }
+    }
+
+    /// Two constants added, then inverted
+    fn circuit() -> (Vec<Node>, Vec<Edge>) {
+        let nodes = vec![
+            Node::from(NodeType::Int(1)),
+            Node::from(NodeType::Int(2)),
+            Node::from(NodeType::Operator(Operator::Add)),
+            Node::from(NodeType::Operator(Operator::BitNot)),
+        ];
+        let edges = vec![
+            edge(&nodes[0], &nodes[2], 0),
+            edge(&nodes[1], &nodes[2], 1),
+            edge(&nodes[2], &nodes[3], 0),
+        ];
+        (nodes, edges)
+    }
+
+    #[test]
+    fn critical_path_goes_through_the_latest_inputs() {
+        let (nodes, edges) = circuit();
+        let wires = [wire(0, 10, 1), wire(1, 10, 3), wire(2, 10, 0)];
+        let timing = Timing::new(&nodes, &edges, &wires);
+
+        // The constants change at tick 1, the second one arrives at the adder at tick 4
+        assert_eq!(timing.critical_path(), (vec![1, 2, 3], 4 + 8 + 1));
+        assert_eq!(timing.races(), vec![(2, 2, 4)]);
+    }
+
+    #[test]
+    fn balancing_delays_the_early_inputs() {
+        let (nodes, edges) = circuit();
+        let mut wires = [wire(0, 10, 1), wire(1, 10, 3), wire(2, 10, 0)];
+        let mut timing = Timing::new(&nodes, &edges, &wires);
+
+        assert_eq!(timing.balance(&mut wires), (2, vec![]));
+        assert!(timing.races().is_empty());
+        assert_eq!(wires[0].delay(), 3);
+        assert_eq!(timing.critical_path().1, 4 + 8 + 1);
+    }
+
+    #[test]
+    fn short_wires_cannot_be_balanced() {
+        let (nodes, edges) = circuit();
+        let mut wires = [wire(0, 3, 0), wire(1, 10, 4), wire(2, 10, 0)];
+        let mut timing = Timing::new(&nodes, &edges, &wires);
+
+        assert_eq!(timing.balance(&mut wires), (0, vec![2]));
+        assert_eq!(timing.races(), vec![(2, 1, 5)]);
+    }
+}
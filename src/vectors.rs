@@ -1,9 +1,25 @@
-use crate::pathfinding::{is_obstacle_nearby, PathNode};
-use std::{collections::HashSet, ops::Sub};
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+use crate::{
+    grid::RoutingGrid,
+    pathfinding::{PathNode, SOURCE_STRENGTH},
+    redstone::{WireBlock, MAX_TOWER_LEVELS},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    ops::{Add, Sub},
+    str::FromStr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub struct Vector3(pub i32, pub i32, pub i32);
 
+impl Add for Vector3 {
+    type Output = Vector3;
+
+    fn add(self, rhs: Self) -> Self::Output {
+        Self(self.0 + rhs.0, self.1 + rhs.1, self.2 + rhs.2)
+    }
+}
+
 impl Sub for Vector3 {
     type Output = Vector3;
 
@@ -12,26 +28,46 @@ impl Sub for Vector3 {
     }
 }
 
+impl FromStr for Vector3 {
+    type Err = String;
+
+    /// A position written `x,y,z`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let coordinates: Vec<i32> = s
+            .split(',')
+            .map(|coordinate| coordinate.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| format!("Invalid position {s:?}, expected \"x,y,z\""))?;
+        match coordinates[..] {
+            [x, y, z] => Ok(Vector3(x, y, z)),
+            _ => Err(format!("Invalid position {s:?}, expected \"x,y,z\"")),
+        }
+    }
+}
+
+/// Extra cost of placing a repeater, so that they are only placed where the signal would be too weak
+const REPEATER_COST: u32 = 20;
+/// Strongest signal a repeater can be placed on. Any stronger would only search the same blocks again
+pub const REPEATER_STRENGTH: u8 = 4;
+/// Extra cost of a slope, so the wires stay flat when they can
+const SLOPE_COST: f32 = 50.0;
+/// Extra cost of each level of a tower
+const TOWER_COST: u32 = 40;
+
 // Implementation of the pathfinding methods
 impl Vector3 {
     pub fn neighbors(
         &self,
         start: &Vector3,
         goal: &Vector3,
-        obstacles: &HashSet<Vector3>,
-        entries: &Vec<Vector3>,
+        grid: &RoutingGrid,
         path_node: &PathNode,
-    ) -> Vec<(Vector3, u32)> {
-        let &Vector3(x, y, z) = self;
+    ) -> Vec<(PathNode, u32)> {
+        // After a tower, the wire continues from its top
+        let Vector3(x, y, z) = *self + Vector3(0, path_node.block.height(), 0);
         let mut neighbors = Vec::new();
 
-        // TODO: Move the bounds values and make them customizable
-        let (start_x, end_x, start_y, end_y, start_z, end_z) = (-10, 50, 140, 180, -30, 30);
-
-        let redstone_step: i32 = (path_node.steps_from_start as i32 - 1) % 14;
-        let previous_direction = path_node
-            .previous
-            .map(|prev| Vector3(x - prev.0, y - prev.1, z - prev.2));
+        let previous_direction = path_node.previous.map(|prev| *self - prev);
 
         let directions = [
             (1, 0, 0, false),  // Right
@@ -53,14 +89,7 @@ impl Vector3 {
             let dist_to_goal = next_pos.distance(goal);
             let dist_to_start = next_pos.distance(start);
 
-            // Out of bounds check
-            if next_pos.0 < start_x
-                || next_pos.0 > end_x
-                || next_pos.1 < start_y
-                || next_pos.1 > end_y
-                || next_pos.2 < start_z
-                || next_pos.2 > end_z
-            {
+            if !grid.contains(next_pos) {
                 continue;
             }
 
@@ -89,57 +118,99 @@ impl Vector3 {
                 }
             }
 
-            // If it is next to the start or goal, allow it
-            if next_pos == *start || next_pos == *goal {
-                neighbors.push((next_pos, 1));
-                continue;
-            }
-
-            // If next to the start or goal, allow it
-            if dist_to_start == 1 || dist_to_goal == 1 {
-                neighbors.push((next_pos, 1));
+            // A repeater or a tower only passes the signal straight on
+            if path_node.block != WireBlock::Redstone
+                && previous_direction != Some(Vector3(dx, dy, dz))
+            {
                 continue;
             }
 
-            // If step == 0, this is a repeater
-            if redstone_step == 0 {
-                // Cannot change direction
-                if let Some(prev_dir) = previous_direction {
-                    if (dx, dy, dz) != (prev_dir.0, prev_dir.1, prev_dir.2) {
-                        continue;
-                    }
+            // The redstone on the next block must still carry the signal
+            let strength = path_node.next_strength();
+            let redstone = PathNode {
+                position: next_pos,
+                previous: Some(*self),
+                strength,
+                block: WireBlock::Redstone,
+            };
+
+            // If it is the start or goal, or next to them, allow it
+            if next_pos == *start || next_pos == *goal || dist_to_start == 1 || dist_to_goal == 1 {
+                if strength > 0 {
+                    neighbors.push((redstone, 1));
                 }
-                // Cannot go in diagonal
-                if is_diagonal {
-                    continue;
-                }
-            }
-
-            // If the next will be repeater, cannot go in diagonal down
-            if redstone_step == 13 && is_diagonal && dy < 0 {
                 continue;
             }
 
-            if !is_obstacle_nearby(obstacles, next_pos) {
+            if !grid.is_obstacle_nearby(next_pos) {
                 // TODO: The cost values and algorithm need to be adjusted. Currently there are some issues in the final paths
-                let mut cost = 1.0;
+                let mut cost = 1.0 + grid.port_cost(next_pos);
 
-                for v in entries {
-                    let dist = next_pos.distance(v);
-                    cost += 1.0 / (dist as f32) * 100.0;
+                if is_diagonal {
+                    cost += SLOPE_COST
                 }
 
-                if is_diagonal {
-                    cost += 50.0
+                let cost = grid.cost(next_pos, cost) as u32;
+                if strength > 0 {
+                    neighbors.push((redstone, cost));
                 }
 
-                neighbors.push((next_pos, cost as u32));
+                // A repeater gives the full strength back once the signal gets weak. It cannot be placed on a slope
+                if !is_diagonal && strength <= REPEATER_STRENGTH {
+                    let repeater = PathNode {
+                        position: next_pos,
+                        previous: Some(*self),
+                        strength: SOURCE_STRENGTH,
+                        block: WireBlock::Repeater(1),
+                    };
+                    neighbors.push((repeater, cost.saturating_add(REPEATER_COST)));
+                }
+
+                // A tower is powered by the redstone pointing straight at it, away from the ports like a slope
+                if !is_diagonal
+                    && path_node.block == WireBlock::Redstone
+                    && previous_direction == Some(Vector3(dx, dy, dz))
+                    && dist_to_goal >= 8
+                    && dist_to_start >= 8
+                {
+                    neighbors.extend(self.towers(next_pos, grid));
+                }
             }
         }
 
         neighbors
     }
 
+    /// The towers that can be built on the position, with their cost
+    fn towers(&self, pos: Vector3, grid: &RoutingGrid) -> Vec<(PathNode, u32)> {
+        let mut towers = Vec::new();
+        let mut cost = 0.0;
+        let mut checked = 0;
+
+        for levels in 1..=MAX_TOWER_LEVELS {
+            let block = WireBlock::Tower(levels);
+            // Only the cells added by this level, the ones below are in the cost of the smaller towers
+            for cell in block.cells(pos).skip(checked) {
+                if grid.is_obstacle_nearby(cell) {
+                    return towers;
+                }
+                cost += grid.cost(cell, 1.0 + grid.port_cost(cell));
+            }
+            checked = block.height() as usize + 1;
+
+            let tower = PathNode {
+                position: pos,
+                previous: Some(*self),
+                strength: SOURCE_STRENGTH,
+                block,
+            };
+            let level_cost = TOWER_COST * levels as u32;
+            towers.push((tower, (cost as u32).saturating_add(level_cost)));
+        }
+
+        towers
+    }
+
     /// Manhattan distance
     pub fn distance(&self, other: &Vector3) -> u32 {
         ((self.0 - other.0).abs() + (self.1 - other.1).abs() + (self.2 - other.2).abs())